@@ -0,0 +1,94 @@
+//! Account checks against `acct_url=`.
+//!
+//! The server is sent the same fields as for authentication, without the
+//! password, and answers with the state of the account in a JSON `account`
+//! field:
+//!
+//! | `account` | Result |
+//! |-----------|--------|
+//! | `valid` | `PAM_SUCCESS` |
+//! | `expired` | `PAM_ACCT_EXPIRED` |
+//! | `password_expired` | `PAM_NEW_AUTHTOK_REQD` |
+//! | anything else, e.g. `disabled` | `PAM_PERM_DENIED` |
+//!
+//! A 2xx response without the field means the account is valid. Other
+//! statuses are mapped as for authentication, except that 401 and 403 deny
+//! access with `PAM_PERM_DENIED`.
+
+use pam::constants::PamResultCode;
+use reqwest::StatusCode;
+
+use status::ResultMap;
+
+/// The result of an account check that got `status` and `body`.
+pub fn result(results: &ResultMap, status: StatusCode, body: &str) -> PamResultCode {
+    if let Some(result) = results
+        .field_result(body)
+        .or_else(|| results.override_for(status))
+    {
+        return result;
+    }
+    match status {
+        s if s.is_success() => account_state(body),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => PamResultCode::PAM_PERM_DENIED,
+        s => results.result(s, body),
+    }
+}
+
+fn account_state(body: &str) -> PamResultCode {
+    let body: serde_json::Value = match serde_json::from_str(body) {
+        Ok(body) => body,
+        Err(_) => return PamResultCode::PAM_SUCCESS,
+    };
+    match body.get("account") {
+        None => PamResultCode::PAM_SUCCESS,
+        Some(state) => match state.as_str() {
+            Some("valid") => PamResultCode::PAM_SUCCESS,
+            Some("expired") => PamResultCode::PAM_ACCT_EXPIRED,
+            Some("password_expired") => PamResultCode::PAM_NEW_AUTHTOK_REQD,
+            _ => PamResultCode::PAM_PERM_DENIED,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::tests::config;
+
+    #[test]
+    fn account_states() {
+        let results = ResultMap::default();
+        let ok = |body| result(&results, StatusCode::OK, body);
+        assert_eq!(ok(""), PamResultCode::PAM_SUCCESS);
+        assert_eq!(ok(r#"{"account": "valid"}"#), PamResultCode::PAM_SUCCESS);
+        assert_eq!(
+            ok(r#"{"account": "expired"}"#),
+            PamResultCode::PAM_ACCT_EXPIRED
+        );
+        assert_eq!(
+            ok(r#"{"account": "password_expired"}"#),
+            PamResultCode::PAM_NEW_AUTHTOK_REQD
+        );
+        assert_eq!(
+            ok(r#"{"account": "disabled"}"#),
+            PamResultCode::PAM_PERM_DENIED
+        );
+        assert_eq!(ok(r#"{"account": null}"#), PamResultCode::PAM_PERM_DENIED);
+    }
+
+    #[test]
+    fn statuses() {
+        let results = config(&["status_404=PERM_DENIED", "result_field=pam"])
+            .unwrap()
+            .results;
+        let status = |code, body| result(&results, StatusCode::from_u16(code).unwrap(), body);
+        assert_eq!(status(403, ""), PamResultCode::PAM_PERM_DENIED);
+        assert_eq!(status(404, ""), PamResultCode::PAM_PERM_DENIED);
+        assert_eq!(status(500, ""), PamResultCode::PAM_AUTHINFO_UNAVAIL);
+        assert_eq!(
+            status(200, r#"{"pam": "USER_UNKNOWN", "account": "valid"}"#),
+            PamResultCode::PAM_USER_UNKNOWN
+        );
+    }
+}
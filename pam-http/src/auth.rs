@@ -0,0 +1,158 @@
+//! Authenticating the module to the server, from the `api_key_file=` and
+//! `hmac_key_file=` arguments.
+//!
+//! An API key is sent as a bearer token. An HMAC key signs each request
+//! instead, so the key itself never goes over the wire: the module sends the
+//! time in `X-Timestamp`, and in `X-Signature` the base64 HMAC-SHA256 of
+//!
+//! ```text
+//! <method>\n<path and query>\n<timestamp>\n<body>
+//! ```
+//!
+//! Servers should reject timestamps too far from their own clock, so that
+//! captured requests can't be replayed later.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use pam::args::Args;
+use pam::secret::Secret;
+use reqwest::blocking::Request;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use sha2::Sha256;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthConfig {
+    pub api_key_file: Option<PathBuf>,
+    pub hmac_key_file: Option<PathBuf>,
+}
+
+impl AuthConfig {
+    /// Reads the settings from the module arguments:
+    ///
+    /// - `api_key_file=<file>`: send the key in this file as a bearer token,
+    /// - `hmac_key_file=<file>`: sign requests with the key in this file.
+    ///
+    /// Trailing whitespace, such as a final newline, isn't part of a key.
+    pub fn from_args(args: &Args) -> AuthConfig {
+        AuthConfig {
+            api_key_file: args.get("api_key_file").map(PathBuf::from),
+            hmac_key_file: args.get("hmac_key_file").map(PathBuf::from),
+        }
+    }
+
+    /// Reads the keys.
+    pub fn keys(&self) -> Result<Keys, String> {
+        let api_key = match self.api_key_file {
+            Some(ref path) => {
+                let key = read_key(path)?;
+                let mut header = key
+                    .expose_str()
+                    .and_then(|key| HeaderValue::from_str(&format!("Bearer {}", key)).ok())
+                    .ok_or_else(|| format!("invalid API key in {}", path.display()))?;
+                header.set_sensitive(true);
+                Some(header)
+            }
+            None => None,
+        };
+        let hmac_key = match self.hmac_key_file {
+            Some(ref path) => Some(read_key(path)?),
+            None => None,
+        };
+        Ok(Keys { api_key, hmac_key })
+    }
+}
+
+fn read_key(path: &Path) -> Result<Secret, String> {
+    let key = Secret::new(
+        fs::read(path).map_err(|err| format!("couldn't read {}: {}", path.display(), err))?,
+    );
+    let len = key
+        .expose()
+        .iter()
+        .rposition(|byte| !byte.is_ascii_whitespace())
+        .map_or(0, |last| last + 1);
+    if len == 0 {
+        return Err(format!("{} is empty", path.display()));
+    }
+    Ok(Secret::from_bytes(&key.expose()[..len]))
+}
+
+/// The keys read by `AuthConfig::keys`.
+#[derive(Debug)]
+pub struct Keys {
+    api_key: Option<HeaderValue>,
+    hmac_key: Option<Secret>,
+}
+
+impl Keys {
+    /// Adds the authentication headers to `request`.
+    pub fn apply(&self, request: &mut Request) -> Result<(), String> {
+        if let Some(ref header) = self.api_key {
+            request.headers_mut().insert(AUTHORIZATION, header.clone());
+        }
+        if let Some(ref key) = self.hmac_key {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_err(|err| err.to_string())?
+                .as_secs();
+            let url = request.url();
+            let path = match url.query() {
+                Some(query) => format!("{}?{}", url.path(), query),
+                None => url.path().to_owned(),
+            };
+            let body = request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .unwrap_or(b"");
+            let signature = sign(key, request.method().as_str(), &path, timestamp, body)?;
+            let headers = request.headers_mut();
+            headers.insert("X-Timestamp", HeaderValue::from(timestamp));
+            headers.insert(
+                "X-Signature",
+                HeaderValue::from_str(&signature).map_err(|err| err.to_string())?,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The signature of a request, in base64.
+fn sign(
+    key: &Secret,
+    method: &str,
+    path: &str,
+    timestamp: u64,
+    body: &[u8],
+) -> Result<String, String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.expose()).map_err(|err| err.to_string())?;
+    mac.update(format!("{}\n{}\n{}\n", method, path, timestamp).as_bytes());
+    mac.update(body);
+    Ok(BASE64.encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures() {
+        // echo -en 'POST\n/login?x=1\n1700000000\n{"user":"alice"}' |
+        //     openssl dgst -sha256 -hmac secret -binary | base64
+        assert_eq!(
+            sign(
+                &Secret::from_bytes(b"secret"),
+                "POST",
+                "/login?x=1",
+                1700000000,
+                br#"{"user":"alice"}"#
+            )
+            .unwrap(),
+            "olZkTRqL6KgnhEke327s4dXmHU9UIT5kzfEg0130HQ8="
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Request bodies for `method=post`.
+
+use url::form_urlencoded;
+
+/// The encoding of a request body, from the `body=` argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyFormat {
+    Json,
+    Form,
+}
+
+impl BodyFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            BodyFormat::Json => "application/json",
+            BodyFormat::Form => "application/x-www-form-urlencoded",
+        }
+    }
+
+    /// Escapes `value` for use inside a string in this format.
+    fn escape(self, value: &str) -> String {
+        match self {
+            BodyFormat::Json => {
+                let quoted = serde_json::Value::from(value).to_string();
+                quoted[1..quoted.len() - 1].to_owned()
+            }
+            BodyFormat::Form => form_urlencoded::byte_serialize(value.as_bytes()).collect(),
+        }
+    }
+}
+
+/// A value that can be sent to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    User,
+    Password,
+    RHost,
+    Tty,
+    Service,
+}
+
+impl Field {
+    const ALL: [Field; 5] = [
+        Field::User,
+        Field::Password,
+        Field::RHost,
+        Field::Tty,
+        Field::Service,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Field::User => "user",
+            Field::Password => "password",
+            Field::RHost => "rhost",
+            Field::Tty => "tty",
+            Field::Service => "service",
+        }
+    }
+}
+
+/// The values of the fields for one request. Items the application didn't
+/// set are `None`.
+#[derive(Debug, Default)]
+pub struct Fields<'a> {
+    pub user: &'a str,
+    pub password: Option<&'a str>,
+    pub rhost: Option<String>,
+    pub tty: Option<String>,
+    pub service: Option<String>,
+}
+
+impl<'a> Fields<'a> {
+    fn get(&self, field: Field) -> Option<&str> {
+        match field {
+            Field::User => Some(self.user),
+            Field::Password => self.password,
+            Field::RHost => self.rhost.as_deref(),
+            Field::Tty => self.tty.as_deref(),
+            Field::Service => self.service.as_deref(),
+        }
+    }
+
+    /// Encodes all fields as a body in `format`. Fields that aren't set are
+    /// `null` in JSON and left out of forms.
+    pub fn encode(&self, format: BodyFormat) -> String {
+        self.encode_with(format, &[])
+    }
+
+    /// Encodes all fields as a body in `format` like `encode`, along with the
+    /// `extra` name-value pairs.
+    pub fn encode_with(&self, format: BodyFormat, extra: &[(&str, &str)]) -> String {
+        let pairs = Field::ALL
+            .iter()
+            .map(|&field| (field.name(), self.get(field)))
+            .chain(extra.iter().map(|&(name, value)| (name, Some(value))));
+        match format {
+            BodyFormat::Json => {
+                let object: serde_json::Map<_, _> = pairs
+                    .map(|(name, value)| (name.to_owned(), value.into()))
+                    .collect();
+                serde_json::Value::Object(object).to_string()
+            }
+            BodyFormat::Form => form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(pairs.filter_map(|(name, value)| value.map(|value| (name, value))))
+                .finish(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// A body given by the `template=` argument, in which `%{name}` is replaced
+/// by the value of the field `name`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("%{") {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unterminated template field {}", &rest[start..]))?;
+            let name = &rest[start + 2..start + end];
+            let field = Field::ALL
+                .iter()
+                .find(|field| field.name() == name)
+                .ok_or_else(|| format!("unknown template field %{{{}}}", name))?;
+            parts.push(Part::Text(rest[..start].to_owned()));
+            parts.push(Part::Field(*field));
+            rest = &rest[start + end + 1..];
+        }
+        parts.push(Part::Text(rest.to_owned()));
+        Ok(Template { parts })
+    }
+
+    /// Fills in the template, escaping each value for `format`. Fields that
+    /// aren't set are left empty.
+    pub fn render(&self, format: BodyFormat, fields: &Fields) -> String {
+        let mut body = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref text) => body.push_str(text),
+                Part::Field(field) => {
+                    let value = fields.get(field).unwrap_or("");
+                    body.push_str(&format.escape(value));
+                }
+            }
+        }
+        body
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Fields<'static> {
+        Fields {
+            user: "alice",
+            password: Some("p\"w&d"),
+            tty: Some("pts/0".to_owned()),
+            ..Fields::default()
+        }
+    }
+
+    #[test]
+    fn encodes_all_fields() {
+        assert_eq!(
+            fields().encode(BodyFormat::Json),
+            r#"{"password":"p\"w&d","rhost":null,"service":null,"tty":"pts/0","user":"alice"}"#
+        );
+        assert_eq!(
+            fields().encode(BodyFormat::Form),
+            "user=alice&password=p%22w%26d&tty=pts%2F0"
+        );
+        assert_eq!(
+            fields().encode_with(BodyFormat::Form, &[("event", "open")]),
+            "user=alice&password=p%22w%26d&tty=pts%2F0&event=open"
+        );
+    }
+
+    #[test]
+    fn renders_templates() {
+        let template =
+            Template::parse(r#"{"login":"%{user}","secret":"%{password}","from":"%{rhost}"}"#)
+                .unwrap();
+        assert_eq!(
+            template.render(BodyFormat::Json, &fields()),
+            r#"{"login":"alice","secret":"p\"w&d","from":""}"#
+        );
+        let template = Template::parse("u=%{user}&p=%{password}").unwrap();
+        assert_eq!(
+            template.render(BodyFormat::Form, &fields()),
+            "u=alice&p=p%22w%26d"
+        );
+        assert!(Template::parse("%{user").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! The offline cache of credentials, from the `cache_file=` and `cache_ttl=`
+//! arguments.
+//!
+//! After each login the server accepts, the password is hashed with argon2
+//! and a random salt and stored with the time of the login, one user per
+//! line:
+//!
+//! ```text
+//! <user>\t<unix time>\t<argon2 PHC string>
+//! ```
+//!
+//! When no server can be reached, a password matching an entry younger than
+//! the TTL is accepted instead. The file must be owned by the user the module
+//! runs as, normally root, and not accessible to anyone else; otherwise it's
+//! ignored.
+//!
+//! Concurrent logins share the file through a `flock` on `<file>.lock`,
+//! taken around each read and each read-modify-write.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use pam::args::Args;
+
+use config::parse_secs;
+
+/// One cached login.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    user: String,
+    time: u64,
+    hash: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+    pub path: PathBuf,
+    pub ttl: Duration,
+}
+
+impl Cache {
+    /// Reads the cache settings from the module arguments:
+    ///
+    /// - `cache_file=<file>`: where to keep the cache, which is off without
+    ///   it,
+    /// - `cache_ttl=<seconds>`: how long after a login it can be repeated
+    ///   offline (default 604800, a week).
+    pub fn from_args(args: &Args) -> Result<Option<Cache>, String> {
+        let path = match args.get("cache_file") {
+            Some(path) => PathBuf::from(path),
+            None => return Ok(None),
+        };
+        let ttl = match args.get("cache_ttl") {
+            Some(secs) => parse_secs("cache_ttl", secs)?,
+            None => Duration::from_secs(7 * 24 * 60 * 60),
+        };
+        Ok(Some(Cache { path, ttl }))
+    }
+
+    /// Whether `password` matches the unexpired entry for `user`.
+    pub fn verify(&self, user: &str, password: &str) -> Result<bool, String> {
+        let now = now()?;
+        let entries = {
+            let _lock = self.lock(libc::LOCK_SH)?;
+            self.load()?
+        };
+        let entry = match entries
+            .iter()
+            .find(|entry| entry.user == user && !self.expired(entry, now))
+        {
+            Some(entry) => entry,
+            None => return Ok(false),
+        };
+        let hash = PasswordHash::new(&entry.hash).map_err(|err| {
+            format!(
+                "invalid hash for {} in {}: {}",
+                user,
+                self.path.display(),
+                err
+            )
+        })?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    }
+
+    /// Stores `password` for `user`, replacing their previous entry and
+    /// dropping expired ones.
+    pub fn store(&self, user: &str, password: &str) -> Result<(), String> {
+        if user.contains(['\t', '\n']) {
+            return Err(format!("can't cache user {:?}", user));
+        }
+        let mut salt = [0; 16];
+        getrandom::getrandom(&mut salt).map_err(|err| err.to_string())?;
+        let salt = SaltString::encode_b64(&salt).map_err(|err| err.to_string())?;
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|err| err.to_string())?
+            .to_string();
+        let _lock = self.lock(libc::LOCK_EX)?;
+        let now = now()?;
+        let mut entries = self.load()?;
+        entries.retain(|entry| entry.user != user && !self.expired(entry, now));
+        entries.push(Entry {
+            user: user.to_owned(),
+            time: now,
+            hash,
+        });
+        self.save(&entries)
+    }
+
+    /// Removes the entry for `user`, e.g. after the server rejected them.
+    pub fn forget(&self, user: &str) -> Result<(), String> {
+        let _lock = self.lock(libc::LOCK_EX)?;
+        let mut entries = self.load()?;
+        let len = entries.len();
+        entries.retain(|entry| entry.user != user);
+        if entries.len() == len {
+            return Ok(());
+        }
+        self.save(&entries)
+    }
+
+    fn expired(&self, entry: &Entry, now: u64) -> bool {
+        now.saturating_sub(entry.time) > self.ttl.as_secs()
+    }
+
+    /// Reads the entries, or none if the file doesn't exist yet. Lines that
+    /// can't be parsed are skipped.
+    fn load(&self) -> Result<Vec<Entry>, String> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(format!("couldn't read {}: {}", self.path.display(), err)),
+        };
+        // Even salted argon2 hashes shouldn't be handed to an offline attack
+        if metadata.uid() != unsafe { libc::geteuid() } || metadata.mode() & 0o077 != 0 {
+            return Err(format!(
+                "{} must be owned by the module's user and accessible to no one else",
+                self.path.display()
+            ));
+        }
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|err| format!("couldn't read {}: {}", self.path.display(), err))?;
+        Ok(contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                Some(Entry {
+                    user: fields.next()?.to_owned(),
+                    time: fields.next()?.parse().ok()?,
+                    hash: fields.next()?.to_owned(),
+                })
+            })
+            .collect())
+    }
+
+    /// Takes `operation`, a shared or exclusive `flock`, on the lock file,
+    /// which is held until the returned file is dropped.
+    ///
+    /// The cache file itself is replaced on every write, so it can't carry
+    /// the lock.
+    fn lock(&self, operation: libc::c_int) -> Result<File, String> {
+        let path = self.sibling(".lock");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(&path)
+            .map_err(|err| format!("couldn't open {}: {}", path.display(), err))?;
+        loop {
+            if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+                return Ok(file);
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(format!("couldn't lock {}: {}", path.display(), err));
+            }
+        }
+    }
+
+    /// The path of the cache file with `suffix` appended.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    /// Replaces the file with `entries`, through a new temporary file so
+    /// that readers never see it half-written. Callers hold the exclusive
+    /// lock.
+    fn save(&self, entries: &[Entry]) -> Result<(), String> {
+        let mut random = [0; 8];
+        getrandom::getrandom(&mut random).map_err(|err| err.to_string())?;
+        let random: String = random.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let tmp = self.sibling(&format!(".{}.{}.tmp", std::process::id(), random));
+        let write = || -> io::Result<()> {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&tmp)?;
+            for entry in entries {
+                writeln!(file, "{}\t{}\t{}", entry.user, entry.time, entry.hash)?;
+            }
+            file.sync_all()?;
+            fs::rename(&tmp, &self.path)
+        };
+        write().map_err(|err| {
+            let _ = fs::remove_file(&tmp);
+            format!("couldn't write {}: {}", self.path.display(), err)
+        })
+    }
+}
+
+fn now() -> Result<u64, String> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn cache(name: &str) -> Cache {
+        let path =
+            std::env::temp_dir().join(format!("pam-http-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        Cache {
+            path,
+            ttl: Duration::from_secs(60),
+        }
+    }
+
+    fn remove(cache: &Cache) {
+        fs::remove_file(&cache.path).unwrap();
+        fs::remove_file(cache.sibling(".lock")).unwrap();
+    }
+
+    #[test]
+    fn stores_and_verifies() {
+        let cache = cache("verify");
+        assert!(!cache.verify("alice", "hunter2").unwrap());
+        cache.store("alice", "hunter2").unwrap();
+        cache.store("bob", "swordfish").unwrap();
+        assert!(cache.verify("alice", "hunter2").unwrap());
+        assert!(!cache.verify("alice", "swordfish").unwrap());
+        assert_eq!(fs::metadata(&cache.path).unwrap().mode() & 0o777, 0o600);
+
+        let contents = fs::read_to_string(&cache.path).unwrap();
+        assert!(!contents.contains("hunter2"));
+        assert!(contents.contains("$argon2id$"));
+
+        cache.forget("alice").unwrap();
+        assert!(!cache.verify("alice", "hunter2").unwrap());
+        assert!(cache.verify("bob", "swordfish").unwrap());
+        remove(&cache);
+    }
+
+    #[test]
+    fn expires() {
+        let cache = cache("expire");
+        cache
+            .save(&[Entry {
+                user: "alice".to_owned(),
+                time: now().unwrap() - 61,
+                hash: "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHQ$aGFzaA".to_owned(),
+            }])
+            .unwrap();
+        assert!(!cache.verify("alice", "hunter2").unwrap());
+        remove(&cache);
+    }
+
+    #[test]
+    fn refuses_readable_files() {
+        let cache = cache("mode");
+        cache.save(&[]).unwrap();
+        fs::set_permissions(&cache.path, Permissions::from_mode(0o644)).unwrap();
+        assert!(cache.verify("alice", "hunter2").is_err());
+        remove(&cache);
+    }
+
+    #[test]
+    fn concurrent_stores() {
+        let cache = cache("concurrent");
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let cache = cache.clone();
+                std::thread::spawn(move || cache.store(&format!("user{}", i), "pw").unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        for i in 0..4 {
+            assert!(cache.verify(&format!("user{}", i), "pw").unwrap());
+        }
+        let dir = cache.path.parent().unwrap();
+        let name = cache.path.file_name().unwrap().to_str().unwrap();
+        assert!(!fs::read_dir(dir).unwrap().any(|entry| {
+            let entry = entry.unwrap().file_name();
+            let entry = entry.to_str().unwrap();
+            entry.starts_with(name) && entry.ends_with(".tmp")
+        }));
+        remove(&cache);
+    }
+}
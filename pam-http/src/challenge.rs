@@ -0,0 +1,192 @@
+//! Server-driven prompts, e.g. for a second factor.
+//!
+//! Instead of a final answer, the server can reply to a login with a
+//! `challenge` object in a JSON response:
+//!
+//! ```json
+//! {"challenge": {"message": "Check your phone", "prompt": "Code: ", "echo": true, "state": "abc"}}
+//! ```
+//!
+//! - `message`: shown to the user first, if present and different from the
+//!   previous challenge's,
+//! - `prompt`: asked for, if present, with the answer shown as it's typed
+//!   if `echo` is `true` (default `false`),
+//! - `poll`: without a `prompt`, the seconds to wait before replying, at
+//!   most `MAX_POLL`,
+//! - `state`: an opaque string sent back with the answer.
+//!
+//! The module then POSTs the user's fields with `state` and `response`, the
+//! answer, to the URL that sent the challenge. The reply to that is handled
+//! like the first response, so the server can ask any number of questions
+//! before giving its result.
+//!
+//! A challenge without a `prompt`, such as a push approval, is replied to
+//! straight away unless it has a `poll`. The server should then either hold
+//! the reply until it has an answer (long-polling) or send the challenge
+//! again with a `poll`, keeping in mind that the login fails after
+//! `MAX_ROUNDS` challenges.
+
+use std::thread;
+use std::time::Duration;
+
+use pam::constants::PamResultCode;
+use pam::conv::Conv;
+use pam::module::PamResult;
+use pam::secret::Secret;
+
+use body::{BodyFormat, Fields};
+
+/// The most challenges a login may be sent before it fails, so that a broken
+/// server can't keep the user prompting forever.
+pub const MAX_ROUNDS: usize = 10;
+
+/// The longest a challenge may ask the module to wait before replying.
+pub const MAX_POLL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Challenge {
+    message: Option<String>,
+    prompt: Option<String>,
+    echo: bool,
+    poll: Option<Duration>,
+    state: String,
+}
+
+impl Challenge {
+    /// The challenge in `body`, if the body is a JSON object with one.
+    ///
+    /// A `challenge` without a string `state` is ignored, as is a `poll`
+    /// that isn't a valid number of seconds.
+    pub fn parse(body: &str) -> Option<Challenge> {
+        let body: serde_json::Value = serde_json::from_str(body).ok()?;
+        let challenge = body.get("challenge")?;
+        let string = |key| {
+            challenge
+                .get(key)
+                .and_then(|value: &serde_json::Value| value.as_str())
+                .map(str::to_owned)
+        };
+        Some(Challenge {
+            message: string("message"),
+            prompt: string("prompt"),
+            echo: challenge
+                .get("echo")
+                .and_then(|echo| echo.as_bool())
+                .unwrap_or(false),
+            poll: challenge
+                .get("poll")
+                .and_then(|poll| poll.as_f64())
+                .and_then(|poll| Duration::try_from_secs_f64(poll).ok())
+                .map(|poll| poll.min(MAX_POLL)),
+            state: string("state")?,
+        })
+    }
+
+    /// Shows the message, unless the `previous` challenge already showed
+    /// it, and asks the prompt, returning the answer if there was a prompt.
+    /// Without a prompt, waits for the `poll` interval instead.
+    pub fn ask(&self, conv: &Conv, previous: Option<&Challenge>) -> PamResult<Option<Secret>> {
+        let repeated = previous.map(|prev| &prev.message) == Some(&self.message);
+        if let Some(ref message) = self.message {
+            if !repeated {
+                conv.info(message)?;
+            }
+        }
+        match self.prompt {
+            Some(ref prompt) if self.echo => {
+                let answer = conv.prompt_visible(prompt)?;
+                Ok(Some(Secret::new(answer.into_bytes())))
+            }
+            Some(ref prompt) => conv.prompt_hidden(prompt).map(Some),
+            None => {
+                if let Some(poll) = self.poll {
+                    thread::sleep(poll);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// The body answering the challenge with `answer`.
+    pub fn reply(
+        &self,
+        format: BodyFormat,
+        fields: &Fields,
+        answer: Option<&Secret>,
+    ) -> PamResult<String> {
+        let answer = match answer {
+            Some(answer) => answer.expose_str().ok_or(PamResultCode::PAM_AUTH_ERR)?,
+            None => "",
+        };
+        Ok(fields.encode_with(format, &[("state", &self.state), ("response", answer)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_challenges() {
+        assert_eq!(
+            Challenge::parse(r#"{"challenge": {"prompt": "Code: ", "echo": true, "state": "s1"}}"#),
+            Some(Challenge {
+                message: None,
+                prompt: Some("Code: ".to_owned()),
+                echo: true,
+                poll: None,
+                state: "s1".to_owned(),
+            })
+        );
+        assert_eq!(
+            Challenge::parse(r#"{"challenge": {"message": "Approve the login", "state": "s2"}}"#),
+            Some(Challenge {
+                message: Some("Approve the login".to_owned()),
+                prompt: None,
+                echo: false,
+                poll: None,
+                state: "s2".to_owned(),
+            })
+        );
+        assert_eq!(
+            Challenge::parse(r#"{"challenge": {"poll": 2.5, "state": "s3"}}"#)
+                .unwrap()
+                .poll,
+            Some(Duration::from_millis(2500))
+        );
+        assert_eq!(
+            Challenge::parse(r#"{"challenge": {"poll": 1e9, "state": "s3"}}"#)
+                .unwrap()
+                .poll,
+            Some(MAX_POLL)
+        );
+        assert_eq!(
+            Challenge::parse(r#"{"challenge": {"poll": -1, "state": "s3"}}"#)
+                .unwrap()
+                .poll,
+            None
+        );
+        assert_eq!(
+            Challenge::parse(r#"{"challenge": {"prompt": "Code: "}}"#),
+            None
+        );
+        assert_eq!(Challenge::parse(r#"{"pam": "SUCCESS"}"#), None);
+        assert_eq!(Challenge::parse(""), None);
+    }
+
+    #[test]
+    fn replies() {
+        let challenge = Challenge::parse(r#"{"challenge": {"state": "s1"}}"#).unwrap();
+        let fields = Fields {
+            user: "alice",
+            ..Fields::default()
+        };
+        let answer = Secret::from_bytes(b"123 456");
+        assert_eq!(
+            challenge
+                .reply(BodyFormat::Form, &fields, Some(&answer))
+                .unwrap(),
+            "user=alice&state=s1&response=123+456"
+        );
+    }
+}
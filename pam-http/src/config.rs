@@ -0,0 +1,297 @@
+//! The module arguments, as given in the PAM service file.
+
+use std::fmt;
+use std::time::Duration;
+
+use pam::args::{self, Args};
+use pam::constants::PamResultCode;
+
+use auth::AuthConfig;
+use body::{BodyFormat, Template};
+use cache::Cache;
+use status::ResultMap;
+use tls::TlsConfig;
+
+/// How credentials are sent to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// A GET request with the credentials in a basic auth header.
+    Get,
+    /// A POST request with the credentials in the body.
+    Post,
+}
+
+/// The order in which repeated `url=` arguments are tried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failover {
+    /// Always start with the first URL.
+    InOrder,
+    /// Start with the URL after the one the previous call started with.
+    RoundRobin,
+}
+
+/// Why the module arguments couldn't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// An invalid argument.
+    Invalid(String),
+    /// An invalid TLS argument. Like TLS files that can't be loaded, these
+    /// fail closed with `PAM_AUTHINFO_UNAVAIL` rather than being treated as
+    /// a broken service file.
+    Tls(String),
+}
+
+impl ConfigError {
+    /// The result a hook fails with.
+    pub fn result(&self) -> PamResultCode {
+        match *self {
+            ConfigError::Invalid(_) => PamResultCode::PAM_SERVICE_ERR,
+            ConfigError::Tls(_) => PamResultCode::PAM_AUTHINFO_UNAVAIL,
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Invalid(ref msg) | ConfigError::Tls(ref msg) => f.write_str(msg),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub urls: Vec<String>,
+    pub acct_urls: Vec<String>,
+    pub session_urls: Vec<String>,
+    pub chauthtok_urls: Vec<String>,
+    pub failover: Failover,
+    pub timeout: Duration,
+    pub connect_timeout: Option<Duration>,
+    pub retries: u32,
+    pub method: Method,
+    pub body: BodyFormat,
+    pub template: Option<Template>,
+    pub results: ResultMap,
+    pub tls: TlsConfig,
+    pub auth: AuthConfig,
+    pub cache: Option<Cache>,
+}
+
+impl Config {
+    /// Reads the configuration from the module arguments:
+    ///
+    /// - `url=<url>`: the endpoint to authenticate against. Repeat it to
+    ///   list fallback servers, tried when a server can't be reached,
+    /// - `acct_url=<url>`: the endpoint to check accounts against, which can
+    ///   also be repeated,
+    /// - `session_url=<url>`: the endpoint to report sessions opening and
+    ///   closing to, which can also be repeated,
+    /// - `chauthtok_url=<url>`: the endpoint to change passwords with, which
+    ///   can also be repeated,
+    /// - `failover=in_order|round_robin`: whether to try the URLs in the
+    ///   order given or spread calls across them (default `in_order`),
+    /// - `timeout=<seconds>`: the limit for each request (default 15),
+    /// - `connect_timeout=<seconds>`: a separate limit for connecting,
+    /// - `retries=<n>`: how many more times to try each URL (default 0),
+    /// - `method=get|post` (default `get`),
+    /// - `body=json|form`: the encoding of the POST body (default `json`),
+    /// - `template=<body>`: the POST body, with `%{user}`, `%{password}`,
+    ///   `%{rhost}`, `%{tty}` and `%{service}` replaced by their (escaped)
+    ///   values. By default the body holds all of them.
+    ///
+    /// and the settings described in `ResultMap::from_args`,
+    /// `TlsConfig::from_args`, `AuthConfig::from_args` and
+    /// `Cache::from_args`.
+    ///
+    /// Returns a description of the first invalid argument on error.
+    pub fn from_args(args: &Args) -> Result<Config, ConfigError> {
+        let tls = TlsConfig::from_args(args).map_err(ConfigError::Tls)?;
+        Config::parse(args, tls).map_err(ConfigError::Invalid)
+    }
+
+    fn parse(args: &Args, tls: TlsConfig) -> Result<Config, String> {
+        let urls: Vec<String> = args.get_all("url").into_iter().map(str::to_owned).collect();
+        let acct_urls = args
+            .get_all("acct_url")
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let session_urls = args
+            .get_all("session_url")
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let chauthtok_urls = args
+            .get_all("chauthtok_url")
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let failover = match args.get("failover") {
+            None | Some("in_order") => Failover::InOrder,
+            Some("round_robin") => Failover::RoundRobin,
+            Some(other) => return Err(format!("invalid failover={}", other)),
+        };
+        let timeout = match args.get("timeout") {
+            Some(secs) => parse_secs("timeout", secs)?,
+            None => Duration::from_secs(15),
+        };
+        let connect_timeout = match args.get("connect_timeout") {
+            Some(secs) => Some(parse_secs("connect_timeout", secs)?),
+            None => None,
+        };
+        let retries = match args.get("retries") {
+            Some(n) => n.parse().map_err(|_| format!("invalid retries={}", n))?,
+            None => 0,
+        };
+        let method = match args.get("method") {
+            None | Some("get") => Method::Get,
+            Some("post") => Method::Post,
+            Some(other) => return Err(format!("invalid method={}", other)),
+        };
+        let body = match args.get("body") {
+            None | Some("json") => BodyFormat::Json,
+            Some("form") => BodyFormat::Form,
+            Some(other) => return Err(format!("invalid body={}", other)),
+        };
+        let template = match args.get("template") {
+            Some(template) => Some(Template::parse(template)?),
+            None => None,
+        };
+        let auth = AuthConfig::from_args(args);
+        if auth.api_key_file.is_some() && !urls.is_empty() && method == Method::Get {
+            // Basic auth already takes the Authorization header. The other
+            // endpoints are always sent POST requests.
+            return Err("api_key_file= needs method=post with url=".to_owned());
+        }
+        Ok(Config {
+            urls,
+            acct_urls,
+            session_urls,
+            chauthtok_urls,
+            failover,
+            timeout,
+            connect_timeout,
+            retries,
+            method,
+            body,
+            template,
+            results: ResultMap::from_args(args)?,
+            tls,
+            auth,
+            cache: Cache::from_args(args)?,
+        })
+    }
+}
+
+/// Parses the `key=` argument as a number of seconds.
+pub fn parse_secs(key: &str, secs: &str) -> Result<Duration, String> {
+    args::parse_secs(secs).ok_or_else(|| format!("invalid {}={}", key, secs))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int};
+
+    /// Parses `args` as if they were given in a service file.
+    pub(crate) fn config(args: &[&str]) -> Result<Config, String> {
+        config_error(args).map_err(|err| err.to_string())
+    }
+
+    pub(crate) fn config_error(args: &[&str]) -> Result<Config, ConfigError> {
+        with_args(args, |args| Config::from_args(&args))
+    }
+
+    /// Calls `f` with `args` as if they were given in a service file.
+    pub(crate) fn with_args<R>(args: &[&str], f: impl FnOnce(Args) -> R) -> R {
+        let owned: Vec<CString> = args.iter().map(|s| CString::new(*s).unwrap()).collect();
+        let argv: Vec<*const c_char> = owned.iter().map(|s| s.as_ptr()).collect();
+        f(unsafe { Args::from_raw(argv.len() as c_int, argv.as_ptr()) })
+    }
+
+    #[test]
+    fn defaults() {
+        let config = config(&["url=http://localhost:3000"]).unwrap();
+        assert_eq!(config.urls, ["http://localhost:3000"]);
+        assert_eq!(config.failover, Failover::InOrder);
+        assert_eq!(config.timeout, Duration::from_secs(15));
+        assert_eq!(config.connect_timeout, None);
+        assert_eq!(config.retries, 0);
+        assert_eq!(config.method, Method::Get);
+        assert_eq!(config.body, BodyFormat::Json);
+        assert!(config.template.is_none());
+    }
+
+    #[test]
+    fn failover() {
+        let config = config(&[
+            "url=http://a",
+            "url=http://b",
+            "failover=round_robin",
+            "timeout=2.5",
+            "connect_timeout=1",
+            "retries=2",
+        ])
+        .unwrap();
+        assert_eq!(config.urls, ["http://a", "http://b"]);
+        assert_eq!(config.failover, Failover::RoundRobin);
+        assert_eq!(config.timeout, Duration::from_millis(2500));
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(config.retries, 2);
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(
+            config(&["url=http://a", "timeout=0"]).unwrap_err(),
+            "invalid timeout=0"
+        );
+        assert_eq!(
+            config_error(&["url=http://a", "timeout=0"])
+                .unwrap_err()
+                .result(),
+            PamResultCode::PAM_SERVICE_ERR
+        );
+        assert_eq!(
+            config_error(&["url=http://a", "tls_min=1.1"])
+                .unwrap_err()
+                .result(),
+            PamResultCode::PAM_AUTHINFO_UNAVAIL
+        );
+        assert_eq!(
+            config(&["url=http://a", "timeout=1e20"]).unwrap_err(),
+            "invalid timeout=1e20"
+        );
+        assert_eq!(
+            config(&["url=http://a", "cache_file=/tmp/c", "cache_ttl=1e300"]).unwrap_err(),
+            "invalid cache_ttl=1e300"
+        );
+        assert_eq!(
+            config(&["url=http://a", "method=put"]).unwrap_err(),
+            "invalid method=put"
+        );
+        assert_eq!(
+            config(&["url=http://a", "template=%{pasword}"]).unwrap_err(),
+            "unknown template field %{pasword}"
+        );
+        assert_eq!(
+            config(&["url=http://a", "api_key_file=/etc/key"]).unwrap_err(),
+            "api_key_file= needs method=post with url="
+        );
+    }
+
+    #[test]
+    fn api_key_without_login() {
+        let config = config(&[
+            "acct_url=http://a",
+            "session_url=http://b",
+            "api_key_file=/etc/key",
+        ])
+        .unwrap();
+        assert!(config.urls.is_empty());
+        assert_eq!(config.method, Method::Get);
+        assert!(config.auth.api_key_file.is_some());
+    }
+}
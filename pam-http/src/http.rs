@@ -0,0 +1,426 @@
+//! Sending requests, with retries and failover between servers.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use pam::constants::PamResultCode;
+use pam::logger::Logger;
+use reqwest::blocking::{Client, Request, RequestBuilder, Response};
+
+use auth::Keys;
+use config::{Config, Failover};
+
+/// The URL the next round-robin call starts with, counted across all calls
+/// in the process.
+static NEXT_URL: AtomicUsize = AtomicUsize::new(0);
+
+/// Why `Backend::send` got no response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// A request couldn't be built or signed, e.g. because of a malformed
+    /// `url=`, so no server was tried.
+    Request,
+    /// No server could be reached: every attempt failed to connect, which
+    /// includes the TLS handshake, or timed out.
+    Unreachable,
+    /// Every attempt failed, not all of them in reaching the server, e.g.
+    /// with too many redirects.
+    Failed,
+}
+
+impl SendError {
+    /// The result a hook fails with: `PAM_SERVICE_ERR` for a request that
+    /// couldn't be made, `PAM_AUTHINFO_UNAVAIL` otherwise, so that the rest
+    /// of the stack can decide whether to carry on without this module.
+    pub fn result(self) -> PamResultCode {
+        match self {
+            SendError::Request => PamResultCode::PAM_SERVICE_ERR,
+            SendError::Unreachable | SendError::Failed => PamResultCode::PAM_AUTHINFO_UNAVAIL,
+        }
+    }
+}
+
+/// A client with the configured TLS settings, timeouts and keys.
+pub struct Backend<'c> {
+    client: Client,
+    config: &'c Config,
+    keys: Keys,
+}
+
+impl<'c> Backend<'c> {
+    pub fn new(config: &'c Config) -> Result<Backend<'c>, String> {
+        let mut builder = Client::builder()
+            .timeout(config.timeout)
+            .use_preconfigured_tls(config.tls.client_config()?);
+        if let Some(timeout) = config.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        let client = builder.build().map_err(|err| err.to_string())?;
+        let keys = config.auth.keys()?;
+        Ok(Backend {
+            client,
+            config,
+            keys,
+        })
+    }
+
+    /// `urls`, e.g. the `url=` arguments, in the order they should be tried.
+    pub fn order<'u>(&self, urls: &'u [String]) -> Vec<&'u str> {
+        let mut urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+        if self.config.failover == Failover::RoundRobin && !urls.is_empty() {
+            let start = NEXT_URL.fetch_add(1, Ordering::Relaxed) % urls.len();
+            urls.rotate_left(start);
+        }
+        urls
+    }
+
+    /// Sends the request `build` makes for each of `urls` in turn, retrying
+    /// each as configured, until a server responds without a 5xx error.
+    ///
+    /// If every server fails with a 5xx error, the last such response is
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SendError::Request` as soon as a request can't be built or
+    /// signed, and otherwise `SendError::Unreachable` or `SendError::Failed`
+    /// if no server responds.
+    pub fn send<F>(&self, log: &Logger, urls: &[&str], build: F) -> Result<Response, SendError>
+    where
+        F: Fn(&Client, &str) -> RequestBuilder,
+    {
+        let mut server_error = None;
+        let mut unreachable = true;
+        for url in urls {
+            for _ in 0..=self.config.retries {
+                let request = self.prepare(build(&self.client, url)).map_err(|msg| {
+                    log.error(&format!("couldn't make a request to {}: {}", url, msg));
+                    SendError::Request
+                })?;
+                match self.client.execute(request) {
+                    Ok(response) if response.status().is_server_error() => {
+                        log.warn(&format!("{} from {}", response.status(), url));
+                        server_error = Some(response);
+                    }
+                    Ok(response) => return Ok(response),
+                    Err(err) => {
+                        unreachable &= err.is_connect() || err.is_timeout();
+                        log.warn(&format!("request failed: {}", err));
+                    }
+                }
+            }
+        }
+        server_error.ok_or_else(|| {
+            log.error("no server could be reached");
+            if unreachable {
+                SendError::Unreachable
+            } else {
+                SendError::Failed
+            }
+        })
+    }
+
+    /// Builds `request` and adds the authentication headers.
+    fn prepare(&self, request: RequestBuilder) -> Result<Request, String> {
+        let mut request = request.build().map_err(|err| err.to_string())?;
+        self.keys.apply(&mut request)?;
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use config::tests::config;
+    use pam::constants::{PAM_PROMPT_ECHO_OFF, PAM_PROMPT_ECHO_ON};
+    use pam::module::PamHandle;
+    use std::cell::{Cell, RefCell};
+    use std::ffi::{CStr, CString};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::os::raw::{c_char, c_int, c_void};
+    use std::ptr;
+    use std::thread::{self, JoinHandle};
+
+    #[repr(C)]
+    struct Message {
+        style: c_int,
+        msg: *const c_char,
+    }
+
+    #[repr(C)]
+    struct Reply {
+        resp: *mut c_char,
+        retcode: c_int,
+    }
+
+    #[repr(C)]
+    struct Conversation {
+        conv: extern "C" fn(c_int, *const *const Message, *mut *mut Reply, *mut c_void) -> c_int,
+        appdata: *mut c_void,
+    }
+
+    #[link(name = "pam")]
+    extern "C" {
+        fn pam_start(
+            service: *const c_char,
+            user: *const c_char,
+            conv: *const Conversation,
+            pamh: *mut *mut PamHandle,
+        ) -> c_int;
+        fn pam_end(pamh: *mut PamHandle, status: c_int) -> c_int;
+    }
+
+    /// What a `Handle`'s application answers, and what it was sent.
+    struct Script {
+        answers: Vec<&'static str>,
+        answered: Cell<usize>,
+        seen: RefCell<Vec<String>>,
+    }
+
+    /// A handle from `pam_start` for `alice`, to log through and converse
+    /// with. The application answers the prompts with `answers` in turn.
+    pub(crate) struct Handle {
+        pamh: *mut PamHandle,
+        script: Box<Script>,
+    }
+
+    impl Handle {
+        pub(crate) fn new(answers: &[&'static str]) -> Handle {
+            let script = Box::new(Script {
+                answers: answers.to_vec(),
+                answered: Cell::new(0),
+                seen: RefCell::new(Vec::new()),
+            });
+            // pam_start copies the conversation, but not the script it
+            // points to
+            let conv = Conversation {
+                conv: converse,
+                appdata: &*script as *const Script as *mut c_void,
+            };
+            let mut pamh = ptr::null_mut();
+            let ret = unsafe {
+                pam_start(
+                    b"pam-http-test\0".as_ptr().cast(),
+                    b"alice\0".as_ptr().cast(),
+                    &conv,
+                    &mut pamh,
+                )
+            };
+            assert_eq!(ret, 0);
+            Handle { pamh, script }
+        }
+
+        pub(crate) fn pamh(&mut self) -> &mut PamHandle {
+            unsafe { &mut *self.pamh }
+        }
+
+        pub(crate) fn log(&self) -> Logger<'_> {
+            Logger::new(unsafe { &*self.pamh })
+        }
+
+        /// The text of every message and prompt the application was sent.
+        pub(crate) fn seen(&self) -> Vec<String> {
+            self.script.seen.borrow().clone()
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            unsafe { pam_end(self.pamh, 0) };
+        }
+    }
+
+    extern "C" fn converse(
+        num_msg: c_int,
+        msg: *const *const Message,
+        resp: *mut *mut Reply,
+        appdata: *mut c_void,
+    ) -> c_int {
+        let script = unsafe { &*(appdata as *const Script) };
+        let num_msg = num_msg as usize;
+        unsafe {
+            let replies = libc::calloc(num_msg, std::mem::size_of::<Reply>()).cast::<Reply>();
+            for i in 0..num_msg {
+                let message = &**msg.add(i);
+                let text = CStr::from_ptr(message.msg).to_string_lossy().into_owned();
+                script.seen.borrow_mut().push(text);
+                if message.style == PAM_PROMPT_ECHO_OFF || message.style == PAM_PROMPT_ECHO_ON {
+                    let n = script.answered.get();
+                    script.answered.set(n + 1);
+                    let answer = CString::new(script.answers[n]).unwrap();
+                    (*replies.add(i)).resp = libc::strdup(answer.as_ptr());
+                }
+            }
+            *resp = replies;
+        }
+        PamResultCode::PAM_SUCCESS as c_int
+    }
+
+    /// Serves `responses`, pairs of a status and a body, to one connection
+    /// each, then returns the requests it got: the request line and the
+    /// body of each.
+    pub(crate) fn serve(
+        responses: &[(u16, &'static str)],
+    ) -> (String, JoinHandle<Vec<(String, String)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let responses = responses.to_vec();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut len = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end().to_ascii_lowercase();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = header.strip_prefix("content-length:") {
+                        len = value.trim().parse().unwrap();
+                    }
+                }
+                let mut request = vec![0; len];
+                reader.read_exact(&mut request).unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+                requests.push((
+                    line.trim_end().to_owned(),
+                    String::from_utf8(request).unwrap(),
+                ));
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    /// A URL nothing listens on, so connecting to it is refused.
+    pub(crate) fn refused_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    }
+
+    #[test]
+    fn round_robin() {
+        let round_robin = config(&[
+            "url=http://a",
+            "url=http://b",
+            "url=http://c",
+            "failover=round_robin",
+        ])
+        .unwrap();
+        let backend = Backend::new(&round_robin).unwrap();
+        let first = backend.order(&round_robin.urls);
+        let second = backend.order(&round_robin.urls);
+        assert_eq!(first.len(), 3);
+        assert_ne!(first[0], second[0]);
+        assert_eq!(second[0], first[1]);
+
+        let in_order = config(&["url=http://a", "url=http://b"]).unwrap();
+        let backend = Backend::new(&in_order).unwrap();
+        assert_eq!(backend.order(&in_order.urls), ["http://a", "http://b"]);
+        assert_eq!(backend.order(&in_order.urls), ["http://a", "http://b"]);
+    }
+
+    #[test]
+    fn unreachable() {
+        let config = config(&["url=http://a", "retries=1"]).unwrap();
+        let backend = Backend::new(&config).unwrap();
+        let handle = Handle::new(&[]);
+        let url = refused_url();
+        assert_eq!(
+            backend
+                .send(&handle.log(), &[&url, &url], |client, url| client.get(url))
+                .unwrap_err(),
+            SendError::Unreachable
+        );
+        assert_eq!(
+            SendError::Unreachable.result(),
+            PamResultCode::PAM_AUTHINFO_UNAVAIL
+        );
+    }
+
+    #[test]
+    fn broken_requests() {
+        let config = config(&["url=http://a"]).unwrap();
+        let backend = Backend::new(&config).unwrap();
+        let handle = Handle::new(&[]);
+        let url = refused_url();
+        // The malformed URL is never followed by the other one
+        assert_eq!(
+            backend
+                .send(&handle.log(), &["not a url", &url], |client, url| client
+                    .get(url))
+                .unwrap_err(),
+            SendError::Request
+        );
+        assert_eq!(SendError::Request.result(), PamResultCode::PAM_SERVICE_ERR);
+    }
+
+    #[test]
+    fn retries() {
+        let (url, server) = serve(&[(503, ""), (502, ""), (200, "ok")]);
+        let config = config(&["url=http://a", "retries=2"]).unwrap();
+        let backend = Backend::new(&config).unwrap();
+        let handle = Handle::new(&[]);
+        let response = backend
+            .send(&handle.log(), &[&url], |client, url| {
+                client.post(url).body("hi")
+            })
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().unwrap(), "ok");
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|request| *request == ("POST / HTTP/1.1".to_owned(), "hi".to_owned())));
+    }
+
+    #[test]
+    fn fails_over() {
+        let (url, server) = serve(&[(200, "ok")]);
+        let config = config(&["url=http://a"]).unwrap();
+        let backend = Backend::new(&config).unwrap();
+        let handle = Handle::new(&[]);
+        let refused = refused_url();
+        let response = backend
+            .send(&handle.log(), &[&refused, &url], |client, url| {
+                client.get(url)
+            })
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn returns_last_server_error() {
+        let (first, first_server) = serve(&[(502, "first")]);
+        let (second, second_server) = serve(&[(503, "second")]);
+        let config = config(&["url=http://a"]).unwrap();
+        let backend = Backend::new(&config).unwrap();
+        let handle = Handle::new(&[]);
+        let refused = refused_url();
+        let response = backend
+            .send(
+                &handle.log(),
+                &[&first, &second, &refused],
+                |client, url| client.get(url),
+            )
+            .unwrap();
+        assert_eq!(response.status(), 503);
+        assert_eq!(response.text().unwrap(), "second");
+        first_server.join().unwrap();
+        second_server.join().unwrap();
+    }
+}
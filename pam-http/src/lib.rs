@@ -1,88 +1,359 @@
+extern crate argon2;
+extern crate base64;
+extern crate getrandom;
+extern crate hmac;
+extern crate libc;
 extern crate pam;
 extern crate reqwest;
+extern crate rustls;
+extern crate rustls_native_certs;
+extern crate rustls_pemfile;
+extern crate serde_json;
+extern crate sha2;
+extern crate url;
+extern crate x509_parser;
 
-use pam::constants::{PamFlag, PamResultCode, PAM_PROMPT_ECHO_OFF};
-use pam::conv::Conv;
-use pam::module::{PamHandle, PamHooks};
-use reqwest::blocking::Client;
-use reqwest::StatusCode;
-use std::collections::HashMap;
-use std::ffi::CStr;
-use std::time::Duration;
+mod account;
+mod auth;
+mod body;
+mod cache;
+mod challenge;
+mod config;
+mod http;
+mod password;
+mod session;
+mod status;
+mod tls;
+
+use pam::constants::PamResultCode;
+use pam::context::HookContext;
+use pam::logger::Logger;
+use pam::module::{PamHooks, PamResult};
 use pam::pam_try;
+use pam::password::chauthtok;
+use pam::session::{close_session, open_session};
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::CONTENT_TYPE;
+
+use body::Fields;
+use challenge::Challenge;
+use config::{Config, Method};
+use http::{Backend, SendError};
+use password::Change;
+use session::Webhook;
 
 struct PamHttp;
-pam::pam_hooks!(PamHttp);
+pam::pam_hooks!(PamHttp, auth, account, session, password);
 
 impl PamHooks for PamHttp {
     // This function performs the task of authenticating the user.
-    fn sm_authenticate(pamh: &mut PamHandle, args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
-        println!("Let's auth over HTTP");
-
-        let args: Vec<_> = args
-            .iter()
-            .map(|s| s.to_string_lossy())
-            .collect();
-        let args: HashMap<&str, &str> = args
-            .iter()
-            .map(|s| {
-                let mut parts = s.splitn(2, '=');
-                (parts.next().unwrap(), parts.next().unwrap_or(""))
-            })
-            .collect();
-
-        let user = pam_try!(pamh.get_user(None));
+    fn sm_authenticate(ctx: &mut HookContext) -> PamResultCode {
+        let config = pam_try!(config(ctx));
+        if config.urls.is_empty() {
+            ctx.log().error("missing url=");
+            return PamResultCode::PAM_SERVICE_ERR;
+        }
 
-        let url: &str = match args.get("url") {
-            Some(url) => url,
-            None => return PamResultCode::PAM_AUTH_ERR,
-        };
+        let user = pam_try!(ctx.user());
 
-        let conv = match pamh.get_item::<Conv>() {
-            Ok(Some(conv)) => conv,
-            Ok(None) => {
-                unreachable!("No conv available");
-            }
+        let conv = match ctx.conv() {
+            Ok(conv) => conv,
             Err(err) => {
-                println!("Couldn't get pam_conv");
+                ctx.log().error("couldn't get pam_conv");
                 return err;
             }
         };
-        let password = pam_try!(conv.send(PAM_PROMPT_ECHO_OFF, "Word, yo: "));
-        let password = match password {
-            Some(password) => Some(pam_try!(password.to_str(), PamResultCode::PAM_AUTH_ERR)),
-            None => None,
-        };
-        println!("Got a password {:?}", password);
-        let status = pam_try!(
-            get_url(url, &user, password),
-            PamResultCode::PAM_AUTH_ERR
-        );
+        let password = pam_try!(conv.prompt_hidden("Word, yo: "));
+        let password = pam_try!(password.expose_str().ok_or(PamResultCode::PAM_AUTH_ERR));
 
-        if !status.is_success() {
-            println!("HTTP Error: {}", status);
-            return PamResultCode::PAM_AUTH_ERR;
+        let fields = pam_try!(fields(ctx, &user, Some(password)));
+        let backend = pam_try!(backend(ctx, &config));
+        let log = ctx.log();
+        let response = backend.send(&log, &backend.order(&config.urls), |client, url| {
+            request(client, url, &config, &fields)
+        });
+        let mut response = match response {
+            Ok(response) => response,
+            // Only a server that can't be reached, not a broken request,
+            // falls back on the cache
+            Err(err @ SendError::Unreachable) => {
+                return offline(&log, &config, &user, password, err.result())
+            }
+            Err(err) => return err.result(),
+        };
+        let fields = Fields {
+            password: None,
+            ..fields
+        };
+        let mut previous = None;
+        for round in 0..challenge::MAX_ROUNDS {
+            let status = response.status();
+            let url = response.url().clone();
+            let body = response.text().unwrap_or_default();
+            let challenge = match Challenge::parse(&body) {
+                Some(challenge) => challenge,
+                None => {
+                    let result = config.results.result(status, &body);
+                    if result != PamResultCode::PAM_SUCCESS {
+                        log.info(&format!("{} from server: {:?}", status, result));
+                    }
+                    // A password alone mustn't get past a second factor offline
+                    remember(&log, &config, &user, password, result, round == 0);
+                    return result;
+                }
+            };
+            let answer = pam_try!(challenge.ask(&conv, previous.as_ref()));
+            let reply = pam_try!(challenge.reply(config.body, &fields, answer.as_ref()));
+            previous = Some(challenge);
+            response = pam_try!(backend
+                .send(&log, &[url.as_str()], |client, url| {
+                    client
+                        .post(url)
+                        .header(CONTENT_TYPE, config.body.content_type())
+                        .body(reply.clone())
+                })
+                .map_err(SendError::result));
         }
-
-        PamResultCode::PAM_SUCCESS
+        log.error(&format!(
+            "gave up after {} challenges from the server",
+            challenge::MAX_ROUNDS
+        ));
+        PamResultCode::PAM_AUTH_ERR
     }
 
-    fn sm_setcred(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+    fn sm_setcred(_ctx: &mut HookContext) -> PamResultCode {
         println!("set credentials");
         PamResultCode::PAM_SUCCESS
     }
 
-    fn acct_mgmt(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
-        println!("account management");
-        PamResultCode::PAM_SUCCESS
+    // Without acct_url=, every account is accepted.
+    fn acct_mgmt(ctx: &mut HookContext) -> PamResultCode {
+        let config = pam_try!(config(ctx));
+        if config.acct_urls.is_empty() {
+            return PamResultCode::PAM_SUCCESS;
+        }
+
+        let user = pam_try!(ctx.user());
+        let fields = pam_try!(fields(ctx, &user, None));
+        let backend = pam_try!(backend(ctx, &config));
+        let log = ctx.log();
+        let body = fields.encode(config.body);
+        let response = pam_try!(backend
+            .send(&log, &backend.order(&config.acct_urls), |client, url| {
+                client
+                    .post(url)
+                    .header(CONTENT_TYPE, config.body.content_type())
+                    .body(body.clone())
+            })
+            .map_err(SendError::result));
+        let status = response.status();
+        let body = response.text().unwrap_or_default();
+        let result = account::result(&config.results, status, &body);
+        if result != PamResultCode::PAM_SUCCESS {
+            log.info(&format!("{} from account server: {:?}", status, result));
+        }
+        result
+    }
+
+    // Without session_url=, sessions aren't reported.
+    fn sm_open_session(ctx: &mut HookContext) -> PamResultCode {
+        let config = pam_try!(config(ctx));
+        if config.session_urls.is_empty() {
+            return PamResultCode::PAM_SUCCESS;
+        }
+        open_session::<Webhook>(ctx)
+    }
+
+    fn sm_close_session(ctx: &mut HookContext) -> PamResultCode {
+        close_session::<Webhook>(ctx)
+    }
+
+    // Without chauthtok_url=, passwords are left to other modules.
+    fn sm_chauthtok(ctx: &mut HookContext) -> PamResultCode {
+        let config = pam_try!(config(ctx));
+        if config.chauthtok_urls.is_empty() {
+            return PamResultCode::PAM_IGNORE;
+        }
+        chauthtok::<Change>(ctx)
     }
 }
 
-fn get_url(url: &str, user: &str, password: Option<&str>) -> reqwest::Result<StatusCode> {
-    let client = Client::builder().timeout(Duration::from_secs(15)).build()?;
-    client
-        .get(url)
-        .basic_auth(user, password)
-        .send()
-        .map(|r| r.status())
+/// Authenticates `user` from the offline cache, if there is one, after no
+/// server could be reached with `err`.
+fn offline(
+    log: &Logger,
+    config: &Config,
+    user: &str,
+    password: &str,
+    err: PamResultCode,
+) -> PamResultCode {
+    let cache = match config.cache {
+        Some(ref cache) => cache,
+        None => return err,
+    };
+    match cache.verify(user, password) {
+        Ok(true) => {
+            log.warn(&format!(
+                "no server could be reached, authenticated {} from the offline cache",
+                user
+            ));
+            PamResultCode::PAM_SUCCESS
+        }
+        Ok(false) => {
+            log.warn(&format!(
+                "no server could be reached and {} isn't in the offline cache",
+                user
+            ));
+            err
+        }
+        Err(msg) => {
+            log.error(&msg);
+            err
+        }
+    }
+}
+
+/// Updates the offline cache, if there is one, with the server's `result`
+/// for `user`: a successful login is cached if `cacheable`, and a definite
+/// rejection removes the user's entry. Other results, such as
+/// `PAM_AUTHINFO_UNAVAIL` from a 5xx response, leave the entry for when the
+/// server is unreachable.
+fn remember(
+    log: &Logger,
+    config: &Config,
+    user: &str,
+    password: &str,
+    result: PamResultCode,
+    cacheable: bool,
+) {
+    let cache = match config.cache {
+        Some(ref cache) => cache,
+        None => return,
+    };
+    let res = match result {
+        PamResultCode::PAM_SUCCESS if cacheable => cache.store(user, password),
+        PamResultCode::PAM_AUTH_ERR
+        | PamResultCode::PAM_USER_UNKNOWN
+        | PamResultCode::PAM_ACCT_EXPIRED
+        | PamResultCode::PAM_PERM_DENIED
+        | PamResultCode::PAM_MAXTRIES => cache.forget(user),
+        _ => Ok(()),
+    };
+    if let Err(msg) = res {
+        log.warn(&msg);
+    }
+}
+
+/// Reads the module arguments, logging any that are invalid. Invalid TLS
+/// settings fail closed with `PAM_AUTHINFO_UNAVAIL`, others with
+/// `PAM_SERVICE_ERR`.
+fn config(ctx: &HookContext) -> PamResult<Config> {
+    Config::from_args(&ctx.args()).map_err(|err| {
+        ctx.log().error(&err.to_string());
+        err.result()
+    })
+}
+
+/// The fields sent to the server about the current request.
+fn fields<'a>(
+    ctx: &HookContext,
+    user: &'a str,
+    password: Option<&'a str>,
+) -> PamResult<Fields<'a>> {
+    let pamh = ctx.handle();
+    Ok(Fields {
+        user,
+        password,
+        rhost: pamh.rhost()?,
+        tty: pamh.tty()?,
+        service: pamh.service()?,
+    })
+}
+
+/// Builds the client, logging why if it can't be, e.g. because a TLS file
+/// is missing.
+fn backend<'c>(ctx: &HookContext, config: &'c Config) -> PamResult<Backend<'c>> {
+    Backend::new(config).map_err(|msg| {
+        ctx.log().error(&msg);
+        PamResultCode::PAM_AUTHINFO_UNAVAIL
+    })
+}
+
+/// Builds the authentication request for `url`. Credentials only ever go in
+/// a header or the body, never in the URL, which servers and proxies tend to
+/// log.
+fn request(client: &Client, url: &str, config: &Config, fields: &Fields) -> RequestBuilder {
+    match config.method {
+        Method::Get => client.get(url).basic_auth(fields.user, fields.password),
+        Method::Post => {
+            let body = match config.template {
+                Some(ref template) => template.render(config.body, fields),
+                None => fields.encode(config.body),
+            };
+            client
+                .post(url)
+                .header(CONTENT_TYPE, config.body.content_type())
+                .body(body)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::tests::with_args;
+    use http::tests::{serve, Handle};
+    use pam::context::Hook;
+    use std::time::{Duration, Instant};
+
+    fn authenticate(handle: &mut Handle, args: &[&str]) -> PamResultCode {
+        with_args(args, |args| {
+            let mut ctx = HookContext::new(handle.pamh(), args, 0, Hook::Authenticate);
+            PamHttp::sm_authenticate(&mut ctx)
+        })
+    }
+
+    #[test]
+    fn relays_challenges() {
+        let (url, server) = serve(&[
+            (200, r#"{"challenge": {"prompt": "Code: ", "state": "s1"}}"#),
+            (200, "{}"),
+        ]);
+        let mut handle = Handle::new(&["hunter2", "123456"]);
+        let result = authenticate(&mut handle, &[&format!("url={}", url), "body=form"]);
+        assert_eq!(result, PamResultCode::PAM_SUCCESS);
+        assert_eq!(handle.seen(), ["Word, yo: ", "Code: "]);
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].0, "GET / HTTP/1.1");
+        assert_eq!(
+            requests[1],
+            (
+                "POST / HTTP/1.1".to_owned(),
+                "user=alice&service=pam-http-test&state=s1&response=123456".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn polls_challenges() {
+        let push = r#"{"challenge": {"message": "Check your phone", "poll": 0.1, "state": "s1"}}"#;
+        let (url, server) = serve(&[(200, push), (200, push), (401, "")]);
+        let mut handle = Handle::new(&["hunter2"]);
+        let start = Instant::now();
+        let result = authenticate(&mut handle, &[&format!("url={}", url)]);
+        assert_eq!(result, PamResultCode::PAM_AUTH_ERR);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(handle.seen(), ["Word, yo: ", "Check your phone"]);
+        assert_eq!(server.join().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_max_rounds() {
+        let push = r#"{"challenge": {"state": "s1"}}"#;
+        let (url, server) = serve(&[(200, push); challenge::MAX_ROUNDS + 1]);
+        let mut handle = Handle::new(&["hunter2"]);
+        let result = authenticate(&mut handle, &[&format!("url={}", url)]);
+        assert_eq!(result, PamResultCode::PAM_AUTH_ERR);
+        assert_eq!(server.join().unwrap().len(), challenge::MAX_ROUNDS + 1);
+    }
 }
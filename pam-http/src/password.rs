@@ -0,0 +1,175 @@
+//! Changing passwords through `chauthtok_url=`.
+//!
+//! The server is called twice, like every module in the `password` stack.
+//! In the preliminary check it's sent the same fields as for an account
+//! check, plus `phase=prelim_check` and `old_password`, and should only
+//! check the old password. If it can't be reached or doesn't accept the
+//! password, the change is abandoned with `PAM_TRY_AGAIN` before the user
+//! types a new one. In the update it's sent `phase=update`, `old_password`
+//! and `new_password`.
+//!
+//! When root changes a password, as in `passwd alice`, it isn't asked for
+//! the old one, so `old_password` is left out of both calls. The server
+//! should then rely on the module's own credentials, e.g. `api_key_file=`.
+//!
+//! Either time, the server can explain a rejection, e.g. by its password
+//! policy, with a `message` field of a JSON response, holding a string or a
+//! list of strings, which is shown to the user. The results of the update
+//! are:
+//!
+//! | Status | Result |
+//! |--------|--------|
+//! | 2xx | `PAM_SUCCESS` |
+//! | 401 | `PAM_AUTHTOK_RECOVERY_ERR`: the old password is wrong |
+//! | 404 | `PAM_USER_UNKNOWN` |
+//! | anything else | `PAM_AUTHTOK_ERR` |
+//!
+//! `status_<code>=` and `result_field=` take precedence, as for
+//! authentication.
+
+use pam::constants::PamResultCode;
+use pam::context::HookContext;
+use pam::module::PamResult;
+use pam::password::PasswordChange;
+use pam::secret::Secret;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::StatusCode;
+
+use status::ResultMap;
+use {backend, config, fields};
+
+/// Password changes sent to the server.
+pub struct Change;
+
+impl PasswordChange for Change {
+    /// Checks that the server can be reached and accepts the old password
+    /// before the user is asked for a new one.
+    fn prelim_check(ctx: &mut HookContext, old: Option<&Secret>) -> PamResult<()> {
+        let mut extra = Vec::new();
+        if let Some(old) = old {
+            let old = old.expose_str().ok_or(PamResultCode::PAM_TRY_AGAIN)?;
+            extra.push(("old_password", old));
+        }
+        match send(ctx, "prelim_check", &extra) {
+            Ok(PamResultCode::PAM_SUCCESS) => Ok(()),
+            _ => Err(PamResultCode::PAM_TRY_AGAIN),
+        }
+    }
+
+    fn update(ctx: &mut HookContext, old: Option<&Secret>, new: &Secret) -> PamResult<()> {
+        let mut extra = Vec::new();
+        if let Some(old) = old {
+            let old = old.expose_str().ok_or(PamResultCode::PAM_AUTHTOK_ERR)?;
+            extra.push(("old_password", old));
+        }
+        let new = new.expose_str().ok_or(PamResultCode::PAM_AUTHTOK_ERR)?;
+        extra.push(("new_password", new));
+        match send(ctx, "update", &extra)? {
+            PamResultCode::PAM_SUCCESS => Ok(()),
+            err => Err(err),
+        }
+    }
+}
+
+/// Sends the user's fields with `phase` and `extra` to the server, showing
+/// the user any messages in the response, and returns the result.
+///
+/// # Errors
+///
+/// Returns `PAM_AUTHTOK_ERR` if no server could be reached.
+fn send(ctx: &HookContext, phase: &str, extra: &[(&str, &str)]) -> PamResult<PamResultCode> {
+    let config = config(ctx)?;
+    let user = ctx.user()?;
+    let fields = fields(ctx, &user, None)?;
+    let backend = backend(ctx, &config).map_err(|_| PamResultCode::PAM_AUTHTOK_ERR)?;
+    let log = ctx.log();
+    let mut pairs = vec![("phase", phase)];
+    pairs.extend_from_slice(extra);
+    let body = fields.encode_with(config.body, &pairs);
+    let response = backend
+        .send(
+            &log,
+            &backend.order(&config.chauthtok_urls),
+            |client, url| {
+                client
+                    .post(url)
+                    .header(CONTENT_TYPE, config.body.content_type())
+                    .body(body.clone())
+            },
+        )
+        .map_err(|_| PamResultCode::PAM_AUTHTOK_ERR)?;
+    let status = response.status();
+    let body = response.text().unwrap_or_default();
+    let conv = ctx.conv()?;
+    for message in messages(&body) {
+        conv.error(&message)?;
+    }
+    let result = result(&config.results, status, &body);
+    if result != PamResultCode::PAM_SUCCESS {
+        log.info(&format!(
+            "{} from password server in {}: {:?}",
+            status, phase, result
+        ));
+    }
+    Ok(result)
+}
+
+/// The result of a password change that got `status` and `body`.
+fn result(results: &ResultMap, status: StatusCode, body: &str) -> PamResultCode {
+    if let Some(result) = results
+        .field_result(body)
+        .or_else(|| results.override_for(status))
+    {
+        return result;
+    }
+    match status {
+        s if s.is_success() => PamResultCode::PAM_SUCCESS,
+        StatusCode::UNAUTHORIZED => PamResultCode::PAM_AUTHTOK_RECOVERY_ERR,
+        StatusCode::NOT_FOUND => PamResultCode::PAM_USER_UNKNOWN,
+        _ => PamResultCode::PAM_AUTHTOK_ERR,
+    }
+}
+
+/// The messages in the `message` field of a JSON `body`, if any.
+fn messages(body: &str) -> Vec<String> {
+    let body: serde_json::Value = match serde_json::from_str(body) {
+        Ok(body) => body,
+        Err(_) => return Vec::new(),
+    };
+    match body.get("message") {
+        Some(serde_json::Value::String(message)) => vec![message.clone()],
+        Some(serde_json::Value::Array(messages)) => messages
+            .iter()
+            .filter_map(|message| message.as_str())
+            .map(str::to_owned)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results() {
+        let results = ResultMap::default();
+        let status = |code| result(&results, StatusCode::from_u16(code).unwrap(), "");
+        assert_eq!(status(204), PamResultCode::PAM_SUCCESS);
+        assert_eq!(status(401), PamResultCode::PAM_AUTHTOK_RECOVERY_ERR);
+        assert_eq!(status(404), PamResultCode::PAM_USER_UNKNOWN);
+        assert_eq!(status(422), PamResultCode::PAM_AUTHTOK_ERR);
+        assert_eq!(status(503), PamResultCode::PAM_AUTHTOK_ERR);
+    }
+
+    #[test]
+    fn policy_messages() {
+        assert_eq!(messages(r#"{"message": "too short"}"#), ["too short"]);
+        assert_eq!(
+            messages(r#"{"message": ["too short", 3, "reused"]}"#),
+            ["too short", "reused"]
+        );
+        assert!(messages(r#"{"error": "too short"}"#).is_empty());
+        assert!(messages("too short").is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+//! Reporting sessions to `session_url=`.
+//!
+//! Opening a session generates an ID and sends an `open` event, and closing
+//! it sends a `close` event with the same ID, so the server can tell which
+//! login each logout belongs to. Both events carry the same fields as an
+//! account check, plus `event` and `session_id`.
+
+use pam::constants::PamResultCode;
+use pam::context::HookContext;
+use pam::module::PamResult;
+use pam::session::Session;
+use reqwest::header::CONTENT_TYPE;
+
+use {backend, config, fields};
+
+/// The session webhook, whose state is the session ID.
+pub struct Webhook;
+
+impl Session for Webhook {
+    type State = String;
+
+    fn open(ctx: &mut HookContext) -> PamResult<String> {
+        let id = new_id().map_err(|err| {
+            ctx.log()
+                .error(&format!("couldn't generate a session ID: {}", err));
+            PamResultCode::PAM_SESSION_ERR
+        })?;
+        send(ctx, "open", &id)?;
+        Ok(id)
+    }
+
+    fn close(ctx: &mut HookContext, id: String) -> PamResult<()> {
+        send(ctx, "close", &id)
+    }
+}
+
+/// A random 128-bit ID, in hex.
+fn new_id() -> Result<String, getrandom::Error> {
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes)?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Sends `event` for the session `id`. Not reaching the server and
+/// unsuccessful responses are both a `PAM_SESSION_ERR`.
+fn send(ctx: &HookContext, event: &str, id: &str) -> PamResult<()> {
+    let config = config(ctx)?;
+    let user = ctx.user()?;
+    let fields = fields(ctx, &user, None)?;
+    let backend = backend(ctx, &config)?;
+    let log = ctx.log();
+    let body = fields.encode_with(config.body, &[("event", event), ("session_id", id)]);
+    let response = backend
+        .send(&log, &backend.order(&config.session_urls), |client, url| {
+            client
+                .post(url)
+                .header(CONTENT_TYPE, config.body.content_type())
+                .body(body.clone())
+        })
+        .map_err(|_| PamResultCode::PAM_SESSION_ERR)?;
+    if !response.status().is_success() {
+        log.error(&format!(
+            "{} from session server for {} of session {}",
+            response.status(),
+            event,
+            id
+        ));
+        return Err(PamResultCode::PAM_SESSION_ERR);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_unique() {
+        let id = new_id().unwrap();
+        assert_eq!(id.len(), 32);
+        assert!(id.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(id, new_id().unwrap());
+    }
+}
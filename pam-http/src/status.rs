@@ -0,0 +1,133 @@
+//! Mapping server responses to PAM result codes.
+
+use pam::args::Args;
+use pam::constants::PamResultCode;
+use reqwest::StatusCode;
+
+/// How responses are turned into result codes, from the `status_<code>=`
+/// and `result_field=` arguments.
+#[derive(Debug, Default)]
+pub struct ResultMap {
+    overrides: Vec<(StatusCode, PamResultCode)>,
+    field: Option<String>,
+}
+
+impl ResultMap {
+    /// Reads the mapping from the module arguments:
+    ///
+    /// - `status_<code>=<result>`: the result for an HTTP status, e.g.
+    ///   `status_404=AUTH_ERR`, replacing the default mapping,
+    /// - `result_field=<name>`: a field of a JSON response body that holds
+    ///   the result, e.g. `{"pam": "NEW_AUTHTOK_REQD"}` with
+    ///   `result_field=pam`. It takes precedence over the status.
+    pub fn from_args(args: &Args) -> Result<ResultMap, String> {
+        let mut overrides = Vec::new();
+        for arg in args.clone().filter_map(|arg| arg.to_str().ok()) {
+            let mut parts = arg.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key.starts_with("status_") => (key, value),
+                _ => continue,
+            };
+            let status = key["status_".len()..]
+                .parse()
+                .ok()
+                .and_then(|code| StatusCode::from_u16(code).ok())
+                .ok_or_else(|| format!("invalid {}=", key))?;
+            let result = value
+                .parse()
+                .map_err(|_| format!("invalid {}={}", key, value))?;
+            overrides.push((status, result));
+        }
+        Ok(ResultMap {
+            overrides,
+            field: args.get("result_field").map(str::to_owned),
+        })
+    }
+
+    /// The result for a response with `status` and `body`.
+    pub fn result(&self, status: StatusCode, body: &str) -> PamResultCode {
+        self.field_result(body)
+            .unwrap_or_else(|| self.status_result(status))
+    }
+
+    /// The result named in the configured field of `body`, if the body is a
+    /// JSON object with that field.
+    pub fn field_result(&self, body: &str) -> Option<PamResultCode> {
+        let field = self.field.as_ref()?;
+        let body: serde_json::Value = serde_json::from_str(body).ok()?;
+        body.get(field)?.as_str()?.parse().ok()
+    }
+
+    /// The result configured for `status` with `status_<code>=`, if any.
+    pub fn override_for(&self, status: StatusCode) -> Option<PamResultCode> {
+        self.overrides
+            .iter()
+            .find(|&&(s, _)| s == status)
+            .map(|&(_, result)| result)
+    }
+
+    fn status_result(&self, status: StatusCode) -> PamResultCode {
+        if let Some(result) = self.override_for(status) {
+            return result;
+        }
+        match status {
+            s if s.is_success() => PamResultCode::PAM_SUCCESS,
+            StatusCode::NOT_FOUND => PamResultCode::PAM_USER_UNKNOWN,
+            StatusCode::LOCKED => PamResultCode::PAM_MAXTRIES,
+            s if s.is_server_error() => PamResultCode::PAM_AUTHINFO_UNAVAIL,
+            // Including 401 and 403
+            _ => PamResultCode::PAM_AUTH_ERR,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::tests::config;
+
+    #[test]
+    fn default_mapping() {
+        let map = ResultMap::default();
+        let result = |status| map.result(StatusCode::from_u16(status).unwrap(), "");
+        assert_eq!(result(200), PamResultCode::PAM_SUCCESS);
+        assert_eq!(result(204), PamResultCode::PAM_SUCCESS);
+        assert_eq!(result(401), PamResultCode::PAM_AUTH_ERR);
+        assert_eq!(result(403), PamResultCode::PAM_AUTH_ERR);
+        assert_eq!(result(404), PamResultCode::PAM_USER_UNKNOWN);
+        assert_eq!(result(423), PamResultCode::PAM_MAXTRIES);
+        assert_eq!(result(503), PamResultCode::PAM_AUTHINFO_UNAVAIL);
+        assert_eq!(result(302), PamResultCode::PAM_AUTH_ERR);
+    }
+
+    #[test]
+    fn configured_mapping() {
+        let map = config(&[
+            "url=http://a",
+            "status_404=AUTH_ERR",
+            "status_409=PAM_NEW_AUTHTOK_REQD",
+            "result_field=pam",
+        ])
+        .unwrap()
+        .results;
+        assert_eq!(
+            map.result(StatusCode::NOT_FOUND, ""),
+            PamResultCode::PAM_AUTH_ERR
+        );
+        assert_eq!(
+            map.result(StatusCode::CONFLICT, "not json"),
+            PamResultCode::PAM_NEW_AUTHTOK_REQD
+        );
+        assert_eq!(
+            map.result(StatusCode::OK, r#"{"pam": "ACCT_EXPIRED"}"#),
+            PamResultCode::PAM_ACCT_EXPIRED
+        );
+        assert_eq!(
+            map.result(StatusCode::OK, r#"{"pam": "BOGUS"}"#),
+            PamResultCode::PAM_SUCCESS
+        );
+
+        assert!(config(&["url=http://a", "status_abc=AUTH_ERR"]).is_err());
+        assert!(config(&["url=http://a", "status_404=NOPE"]).is_err());
+    }
+}
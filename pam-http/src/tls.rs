@@ -0,0 +1,251 @@
+//! TLS settings, from the `ca_file=`, `client_cert=`, `client_key=`,
+//! `pin_sha256=` and `tls_min=` arguments.
+//!
+//! The rustls configuration is built here rather than through reqwest's
+//! builder so that pinned keys are checked during the handshake, before any
+//! credentials are sent.
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use pam::args::Args;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::version::{TLS12, TLS13};
+use rustls::{Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName};
+use rustls_pemfile::Item;
+use sha2::{Digest, Sha256};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    pub ca_file: Option<PathBuf>,
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+    pub pins: Vec<[u8; 32]>,
+    pub min_version: TlsVersion,
+}
+
+impl TlsConfig {
+    /// Reads the TLS settings from the module arguments:
+    ///
+    /// - `ca_file=<pem>`: trust only the CAs in this file, instead of the
+    ///   system's,
+    /// - `client_cert=<pem>` and `client_key=<pem>`: authenticate to the
+    ///   server with this certificate,
+    /// - `pin_sha256=<base64>[,<base64>...]`: require the server's public key
+    ///   to have one of these SHA-256 hashes, as with curl's `--pinnedpubkey`,
+    /// - `tls_min=1.2|1.3`: the lowest TLS version to accept (default 1.2,
+    ///   the lowest supported, so older versions are rejected).
+    pub fn from_args(args: &Args) -> Result<TlsConfig, String> {
+        let client_cert = match (args.get("client_cert"), args.get("client_key")) {
+            (Some(cert), Some(key)) => Some((PathBuf::from(cert), PathBuf::from(key))),
+            (None, None) => None,
+            _ => return Err("client_cert= and client_key= must be given together".to_owned()),
+        };
+        let pins = match args.get("pin_sha256") {
+            Some(pins) => pins.split(',').map(parse_pin).collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        let min_version = match args.get("tls_min") {
+            None | Some("1.2") => TlsVersion::Tls12,
+            Some("1.3") => TlsVersion::Tls13,
+            Some(other) => return Err(format!("invalid tls_min={}", other)),
+        };
+        Ok(TlsConfig {
+            ca_file: args.get("ca_file").map(PathBuf::from),
+            client_cert,
+            pins,
+            min_version,
+        })
+    }
+
+    /// Builds the rustls configuration, reading the certificate and key
+    /// files.
+    pub fn client_config(&self) -> Result<ClientConfig, String> {
+        let mut roots = RootCertStore::empty();
+        match self.ca_file {
+            Some(ref path) => {
+                for cert in read_certs(path)? {
+                    roots
+                        .add(&cert)
+                        .map_err(|err| format!("invalid CA in {}: {}", path.display(), err))?;
+                }
+            }
+            None => {
+                let native = rustls_native_certs::load_native_certs()
+                    .map_err(|err| format!("couldn't load system CAs: {}", err))?;
+                roots.add_parsable_certificates(&native);
+            }
+        }
+
+        let versions: &[_] = match self.min_version {
+            TlsVersion::Tls12 => &[&TLS12, &TLS13],
+            TlsVersion::Tls13 => &[&TLS13],
+        };
+        let builder = ClientConfig::builder()
+            .with_safe_default_cipher_suites()
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(versions)
+            .map_err(|err| err.to_string())?
+            .with_custom_certificate_verifier(Arc::new(PinningVerifier {
+                inner: WebPkiVerifier::new(roots, None),
+                pins: self.pins.clone(),
+            }));
+
+        match self.client_cert {
+            Some((ref cert, ref key)) => builder
+                .with_client_auth_cert(read_certs(cert)?, read_key(key)?)
+                .map_err(|err| format!("invalid client_cert=: {}", err)),
+            None => Ok(builder.with_no_client_auth()),
+        }
+    }
+}
+
+fn parse_pin(pin: &str) -> Result<[u8; 32], String> {
+    let pin = pin.trim_start_matches("sha256//");
+    let hash = BASE64
+        .decode(pin)
+        .map_err(|_| format!("invalid pin_sha256={}", pin))?;
+    <[u8; 32]>::try_from(hash.as_slice()).map_err(|_| format!("invalid pin_sha256={}", pin))
+}
+
+fn read_pem(path: &PathBuf) -> Result<Vec<Item>, String> {
+    let file =
+        File::open(path).map_err(|err| format!("couldn't open {}: {}", path.display(), err))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|err| format!("couldn't read {}: {}", path.display(), err))
+}
+
+fn read_certs(path: &PathBuf) -> Result<Vec<Certificate>, String> {
+    let certs: Vec<_> = read_pem(path)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certs.is_empty() {
+        return Err(format!("no certificates in {}", path.display()));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &PathBuf) -> Result<PrivateKey, String> {
+    read_pem(path)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("no private key in {}", path.display()))
+}
+
+/// Verifies certificates as usual, then checks the server's key against
+/// the pins, if any.
+struct PinningVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            scts,
+            ocsp_response,
+            now,
+        )?;
+        if self.pins.is_empty() {
+            return Ok(verified);
+        }
+        let spki = public_key_info(&end_entity.0)
+            .ok_or_else(|| rustls::Error::General("unparsable certificate".to_owned()))?;
+        let hash: [u8; 32] = Sha256::digest(spki).into();
+        if self.pins.contains(&hash) {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(format!(
+                "public key sha256//{} is not pinned",
+                BASE64.encode(hash)
+            )))
+        }
+    }
+}
+
+/// The DER-encoded `SubjectPublicKeyInfo` of an X.509 certificate, which is
+/// what key pins are hashes of.
+fn public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    match X509Certificate::from_der(cert) {
+        Ok((&[], cert)) => Some(cert.tbs_certificate.subject_pki.raw),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::tests::config;
+
+    /// A server certificate, and the pin of its key as printed by
+    /// `openssl x509 -pubkey | openssl pkey -pubin -outform der | openssl
+    /// dgst -sha256 -binary | base64`.
+    const CERT: &str = include_str!("../testdata/cert.pem");
+    const CERT_PIN: &str = include_str!("../testdata/cert.pin");
+
+    #[test]
+    fn hashes_the_public_key() {
+        let der = match rustls_pemfile::read_one(&mut CERT.as_bytes()).unwrap() {
+            Some(Item::X509Certificate(der)) => der,
+            _ => panic!("not a certificate"),
+        };
+        let spki = public_key_info(&der).unwrap();
+        let pin = parse_pin(CERT_PIN.trim()).unwrap();
+        assert_eq!(<[u8; 32]>::from(Sha256::digest(spki)), pin);
+        assert_eq!(public_key_info(&der[..der.len() / 2]), None);
+        assert_eq!(public_key_info(&[der.as_slice(), b"\0"].concat()), None);
+        assert_eq!(public_key_info(&[0x30, 0x84, 0xff, 0xff, 0xff, 0xff]), None);
+        assert_eq!(public_key_info(b""), None);
+    }
+
+    #[test]
+    fn settings() {
+        let tls = config(&[
+            "url=https://a",
+            "ca_file=/etc/ca.pem",
+            "pin_sha256=sha256//47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            "tls_min=1.3",
+        ])
+        .unwrap()
+        .tls;
+        assert_eq!(tls.ca_file, Some(PathBuf::from("/etc/ca.pem")));
+        assert_eq!(tls.pins, [<[u8; 32]>::from(Sha256::digest(b""))]);
+        assert_eq!(tls.min_version, TlsVersion::Tls13);
+
+        assert!(config(&["url=https://a", "client_cert=/c.pem"]).is_err());
+        assert!(config(&["url=https://a", "pin_sha256=abc"]).is_err());
+        assert!(config(&["url=https://a", "tls_min=2"]).is_err());
+        assert!(config(&["url=https://a", "tls_min=1.0"]).is_err());
+    }
+}
@@ -0,0 +1,219 @@
+//! Procedural macros for writing pam modules with
+//! [pam-bindings](https://crates.io/crates/pam-bindings).
+//!
+//! These are re-exported by the `pam` crate when its `macros` feature is
+//! enabled, and should be used from there.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro2::{Span, TokenStream};
+use syn::{FnArg, ImplItem, ItemImpl};
+
+/// The `PamHooks` methods, with the management group each belongs to, the
+/// `pam_sm_*` symbol it is exported as and its `pam::context::Hook` variant.
+const HOOKS: &[(&str, &str, &str, &str)] = &[
+    ("account", "acct_mgmt", "pam_sm_acct_mgmt", "AcctMgmt"),
+    (
+        "auth",
+        "sm_authenticate",
+        "pam_sm_authenticate",
+        "Authenticate",
+    ),
+    ("password", "sm_chauthtok", "pam_sm_chauthtok", "ChAuthTok"),
+    (
+        "session",
+        "sm_close_session",
+        "pam_sm_close_session",
+        "CloseSession",
+    ),
+    (
+        "session",
+        "sm_open_session",
+        "pam_sm_open_session",
+        "OpenSession",
+    ),
+    ("auth", "sm_setcred", "pam_sm_setcred", "SetCred"),
+];
+
+/// Generates the `extern "C"` entrypoints needed by PAM from an
+/// `impl PamHooks` block.
+///
+/// Unlike `pam_hooks!`, only the management groups with a hook implemented in
+/// the block are exported, so PAM sees exactly the management functions the
+/// module supports. A group is exported whole: implementing `sm_authenticate`
+/// also exports `pam_sm_setcred`, which returns `PAM_IGNORE` unless it is
+/// implemented too, as PAM calls both for `auth` modules. Each entrypoint
+/// extracts the module arguments and turns a panic in the hook
+/// into a logged `PAM_SYSTEM_ERR` instead of unwinding into the application.
+///
+/// ```
+/// extern crate pam;
+///
+/// use pam::constants::PamResultCode;
+/// use pam::context::HookContext;
+/// use pam::module::PamHooks;
+/// use pam::pam_module;
+///
+/// # fn main() {}
+/// struct MyPamModule;
+///
+/// #[pam_module]
+/// impl PamHooks for MyPamModule {
+///     fn sm_authenticate(ctx: &mut HookContext) -> PamResultCode {
+///         println!("Everybody is authenticated!");
+///         PamResultCode::PAM_SUCCESS
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn pam_module(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let item = syn::parse_macro_input!(item as ItemImpl);
+    expand(attr.into(), item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(attr: TokenStream, item: ItemImpl) -> syn::Result<TokenStream> {
+    if !attr.is_empty() {
+        return Err(syn::Error::new_spanned(
+            attr,
+            "#[pam_module] does not take any arguments",
+        ));
+    }
+    match item.trait_ {
+        Some((None, ref path, _))
+            if path.segments.last().is_some_and(|s| s.ident == "PamHooks") => {}
+        _ => {
+            return Err(syn::Error::new(
+                Span::call_site(),
+                "#[pam_module] must be applied to an `impl PamHooks for ...` block",
+            ))
+        }
+    }
+
+    let mut groups = Vec::new();
+    for impl_item in &item.items {
+        let method = match *impl_item {
+            ImplItem::Fn(ref method) => method,
+            _ => continue,
+        };
+        let sig = &method.sig;
+        let name = sig.ident.to_string();
+        match HOOKS.iter().find(|&&(_, hook, _, _)| hook == name) {
+            Some(&(group, _, _, _)) => groups.push(group),
+            None => {
+                let expected: Vec<_> = HOOKS.iter().map(|&(_, hook, _, _)| hook).collect();
+                return Err(syn::Error::new_spanned(
+                    &sig.ident,
+                    format!(
+                        "`{}` is not a PAM hook, expected one of: {}",
+                        name,
+                        expected.join(", ")
+                    ),
+                ));
+            }
+        }
+        if sig.inputs.len() != 1
+            || sig
+                .inputs
+                .iter()
+                .any(|arg| matches!(arg, FnArg::Receiver(_)))
+        {
+            return Err(syn::Error::new_spanned(
+                &sig.inputs,
+                "PAM hooks take `(ctx: &mut HookContext)`",
+            ));
+        }
+    }
+
+    let self_ty = &item.self_ty;
+    let entrypoints = HOOKS
+        .iter()
+        .filter(|&&(group, _, _, _)| groups.contains(&group))
+        .map(|&(_, hook, symbol, variant)| {
+            let hook = syn::Ident::new(hook, Span::call_site());
+            let symbol = syn::Ident::new(symbol, Span::call_site());
+            let variant = syn::Ident::new(variant, Span::call_site());
+            quote! {
+                #[no_mangle]
+                #[allow(clippy::not_unsafe_ptr_arg_deref)]
+                pub extern "C" fn #symbol(
+                    pamh: &mut ::pam::module::PamHandle,
+                    flags: ::pam::constants::PamFlag,
+                    argc: ::std::os::raw::c_int,
+                    argv: *const *const ::std::os::raw::c_char,
+                ) -> ::pam::constants::PamResultCode {
+                    unsafe {
+                        ::pam::macros::dispatch(
+                            pamh,
+                            flags,
+                            argc,
+                            argv,
+                            ::pam::context::Hook::#variant,
+                            <#self_ty as ::pam::module::PamHooks>::#hook,
+                        )
+                    }
+                }
+            }
+        });
+
+    Ok(quote! {
+        #item
+        #(#entrypoints)*
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(item: TokenStream) -> String {
+        match expand(TokenStream::new(), syn::parse2(item).unwrap()) {
+            Ok(tokens) => tokens.to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn exports_only_implemented_groups() {
+        let expanded = expand_str(quote! {
+            impl PamHooks for Foo {
+                fn sm_authenticate(ctx: &mut HookContext) -> PamResultCode {
+                    PamResultCode::PAM_SUCCESS
+                }
+            }
+        });
+        assert!(expanded.contains("fn pam_sm_authenticate"));
+        assert!(expanded.contains("fn pam_sm_setcred"));
+        assert!(!expanded.contains("fn pam_sm_acct_mgmt"));
+        assert!(!expanded.contains("fn pam_sm_open_session"));
+    }
+
+    #[test]
+    fn rejects_unknown_hooks_and_bad_signatures() {
+        let expanded = expand_str(quote! {
+            impl PamHooks for Foo {
+                fn sm_authenticat(ctx: &mut HookContext) -> PamResultCode {
+                    PamResultCode::PAM_SUCCESS
+                }
+            }
+        });
+        assert!(expanded.starts_with("`sm_authenticat` is not a PAM hook"));
+
+        let expanded = expand_str(quote! {
+            impl PamHooks for Foo {
+                fn sm_authenticate(&self) -> PamResultCode {
+                    PamResultCode::PAM_SUCCESS
+                }
+            }
+        });
+        assert!(expanded.starts_with("PAM hooks take"));
+    }
+}
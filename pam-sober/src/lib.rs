@@ -1,35 +1,29 @@
 extern crate pam;
 extern crate rand;
 
-use pam::constants::{PamFlag, PamResultCode, PAM_PROMPT_ECHO_ON};
-use pam::conv::Conv;
-use pam::module::{PamHandle, PamHooks};
+use pam::constants::{PamResultCode, PAM_PROMPT_ECHO_ON};
+use pam::context::HookContext;
+use pam::module::PamHooks;
+use pam::{pam_module, pam_try};
 use rand::Rng;
-use std::ffi::CStr;
 use std::str::FromStr;
-use pam::pam_try;
 
 struct PamSober;
-pam::pam_hooks!(PamSober);
 
+#[pam_module]
 impl PamHooks for PamSober {
     // This function performs the task of authenticating the user.
-    fn sm_authenticate(pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+    fn sm_authenticate(ctx: &mut HookContext) -> PamResultCode {
         println!("Let's make sure you're sober enough to perform basic addition");
 
-        /* TODO: use args to change difficulty ;-)
-        let args: HashMap<&str, &str> = args.iter().map(|s| {
-            let mut parts = s.splitn(2, "=");
-            (parts.next().unwrap(), parts.next().unwrap_or(""))
-        }).collect();
-        */
+        // TODO: use args to change difficulty ;-)
+        // let difficulty = ctx.args().get("difficulty");
 
         // TODO: maybe we can change difficulty base on user?
-        // let user = pam_try!(pam.get_user(None));
+        // let user = pam_try!(ctx.user());
 
-        let conv = match pamh.get_item::<Conv>() {
-            Ok(Some(conv)) => conv,
-            Ok(None) => todo!(),
+        let conv = match ctx.conv() {
+            Ok(conv) => conv,
             Err(err) => {
                 println!("Couldn't get pam_conv");
                 return err;
@@ -61,12 +55,12 @@ impl PamHooks for PamSober {
         }
     }
 
-    fn sm_setcred(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+    fn sm_setcred(_ctx: &mut HookContext) -> PamResultCode {
         println!("set credentials");
         PamResultCode::PAM_SUCCESS
     }
 
-    fn acct_mgmt(_pamh: &mut PamHandle, _args: Vec<&CStr>, _flags: PamFlag) -> PamResultCode {
+    fn acct_mgmt(_ctx: &mut HookContext) -> PamResultCode {
         println!("account management");
         PamResultCode::PAM_SUCCESS
     }
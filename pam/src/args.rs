@@ -0,0 +1,146 @@
+//! The arguments a module is configured with in the PAM service file.
+
+use libc::{c_char, c_int};
+use std::ffi::CStr;
+use std::fmt;
+use std::time::Duration;
+
+/// The arguments given to a module in its PAM service file, e.g.
+/// `debug url=https://example.com` in
+///
+/// ```text
+/// auth required pam_example.so debug url=https://example.com
+/// ```
+///
+/// `Args` borrows PAM's own `argv` for the duration of a single hook call. It
+/// is an iterator over the raw arguments, and also provides lookups for the
+/// common `flag` and `key=value` forms.
+#[derive(Clone)]
+pub struct Args<'call> {
+    argv: &'call [*const c_char],
+}
+
+impl<'call> Args<'call> {
+    /// Wraps the `argc`/`argv` pair a `pam_sm_*` function was called with.
+    ///
+    /// # Safety
+    ///
+    /// Unless `argc` is zero, `argv` must point to `argc` valid nul-terminated
+    /// strings that live for `'call`.
+    pub unsafe fn from_raw(argc: c_int, argv: *const *const c_char) -> Args<'call> {
+        let argv = if argc <= 0 || argv.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(argv, argc as usize)
+        };
+        Args { argv }
+    }
+
+    /// The value of the first `key=value` argument with the given key.
+    ///
+    /// Arguments that are not valid UTF-8 are ignored.
+    pub fn get(&self, key: &str) -> Option<&'call str> {
+        self.clone().filter_map(|arg| value(arg, key)).next()
+    }
+
+    /// The values of every `key=value` argument with the given key, in the
+    /// order they were given, e.g. for repeated `url=` arguments.
+    ///
+    /// Arguments that are not valid UTF-8 are ignored.
+    pub fn get_all(&self, key: &str) -> Vec<&'call str> {
+        self.clone().filter_map(|arg| value(arg, key)).collect()
+    }
+
+    /// Whether the bare flag `name` (e.g. `debug`) was given.
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.clone().any(|arg| arg.to_bytes() == name.as_bytes())
+    }
+}
+
+/// The value of `arg` if it is of the form `key=value`.
+fn value<'call>(arg: &'call CStr, key: &str) -> Option<&'call str> {
+    let arg = arg.to_str().ok()?;
+    let mut parts = arg.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(k), Some(v)) if k == key => Some(v),
+        _ => None,
+    }
+}
+
+/// Parses a positive, possibly fractional, number of seconds, as in
+/// `timeout=2.5`. Values too large for a `Duration` are invalid.
+pub fn parse_secs(secs: &str) -> Option<Duration> {
+    match secs.parse::<f64>() {
+        Ok(secs) if secs > 0.0 => Duration::try_from_secs_f64(secs).ok(),
+        _ => None,
+    }
+}
+
+impl<'call> Iterator for Args<'call> {
+    type Item = &'call CStr;
+
+    fn next(&mut self) -> Option<&'call CStr> {
+        let (first, rest) = self.argv.split_first()?;
+        self.argv = rest;
+        Some(unsafe { CStr::from_ptr(*first) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.argv.len(), Some(self.argv.len()))
+    }
+}
+
+impl<'call> ExactSizeIterator for Args<'call> {}
+
+impl<'call> fmt::Debug for Args<'call> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    /// Calls `f` with `args` as if they were given in a service file.
+    pub(crate) fn with_args<R>(args: &[&str], f: impl FnOnce(Args) -> R) -> R {
+        let owned: Vec<CString> = args.iter().map(|s| CString::new(*s).unwrap()).collect();
+        let argv: Vec<*const c_char> = owned.iter().map(|s| s.as_ptr()).collect();
+        f(unsafe { Args::from_raw(argv.len() as c_int, argv.as_ptr()) })
+    }
+
+    #[test]
+    fn lookups() {
+        let args = ["debug", "url=http://a?b=c", "url=http://d", "timeout="];
+        with_args(&args, |args| {
+            assert_eq!(args.len(), 4);
+            assert!(args.has_flag("debug"));
+            assert!(!args.has_flag("url"));
+            assert_eq!(args.get("url"), Some("http://a?b=c"));
+            assert_eq!(args.get_all("url"), ["http://a?b=c", "http://d"]);
+            assert!(args.get_all("debug").is_empty());
+            assert_eq!(args.get("timeout"), Some(""));
+            assert_eq!(args.get("debug"), None);
+            assert_eq!(args.last().map(CStr::to_bytes), Some(&b"timeout="[..]));
+        });
+    }
+
+    #[test]
+    fn empty() {
+        let mut args = unsafe { Args::from_raw(0, std::ptr::null()) };
+        assert_eq!(args.next(), None);
+        assert_eq!(args.get("url"), None);
+    }
+
+    #[test]
+    fn seconds() {
+        assert_eq!(parse_secs("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_secs("0"), None);
+        assert_eq!(parse_secs("-1"), None);
+        assert_eq!(parse_secs("soon"), None);
+        assert_eq!(parse_secs("1e20"), None);
+        assert_eq!(parse_secs("inf"), None);
+        assert_eq!(parse_secs("NaN"), None);
+    }
+}
@@ -1,4 +1,5 @@
 use libc::{c_int, c_uint};
+use std::str::FromStr;
 
 // TODO: Import constants from C header file at compile time.
 
@@ -16,6 +17,11 @@ pub const PAM_REINITIALIZE_CRED: PamFlag = 0x0008;
 pub const PAM_REFRESH_CRED: PamFlag = 0x0010;
 pub const PAM_CHANGE_EXPIRED_AUTHTOK: PamFlag = 0x0020;
 
+// Flags passed to pam_sm_chauthtok by Linux-PAM itself
+// see /usr/include/security/_pam_types.h
+pub const PAM_PRELIM_CHECK: PamFlag = 0x4000;
+pub const PAM_UPDATE_AUTHTOK: PamFlag = 0x2000;
+
 // Message styles
 pub const PAM_PROMPT_ECHO_OFF: PamMessageStyle = 1;
 pub const PAM_PROMPT_ECHO_ON: PamMessageStyle = 2;
@@ -25,10 +31,17 @@ pub const PAM_TEXT_INFO: PamMessageStyle = 4;
 pub const PAM_RADIO_TYPE: PamMessageStyle = 5;
 pub const PAM_BINARY_PROMPT: PamMessageStyle = 7;
 
+// Binary prompt control bytes
+// see /usr/include/security/pam_client.h
+pub const PAM_BPC_OK: u8 = 0x01;
+pub const PAM_BPC_SELECT: u8 = 0x02;
+pub const PAM_BPC_DONE: u8 = 0x03;
+pub const PAM_BPC_FAIL: u8 = 0x04;
+
 // The Linux-PAM return values
 // see /usr/include/security/_pam_types.h
 #[allow(non_camel_case_types, dead_code)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub enum PamResultCode {
     PAM_SUCCESS = 0,
@@ -64,3 +77,67 @@ pub enum PamResultCode {
     PAM_CONV_AGAIN = 30,
     PAM_INCOMPLETE = 31,
 }
+
+/// Parses the name of a result code, with or without its `PAM_` prefix,
+/// e.g. `"PAM_NEW_AUTHTOK_REQD"` or `"NEW_AUTHTOK_REQD"`.
+impl FromStr for PamResultCode {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<PamResultCode, ()> {
+        let name = if name.starts_with("PAM_") {
+            name.to_owned()
+        } else {
+            format!("PAM_{}", name)
+        };
+        Ok(match name.as_str() {
+            "PAM_SUCCESS" => PamResultCode::PAM_SUCCESS,
+            "PAM_OPEN_ERR" => PamResultCode::PAM_OPEN_ERR,
+            "PAM_SYMBOL_ERR" => PamResultCode::PAM_SYMBOL_ERR,
+            "PAM_SERVICE_ERR" => PamResultCode::PAM_SERVICE_ERR,
+            "PAM_SYSTEM_ERR" => PamResultCode::PAM_SYSTEM_ERR,
+            "PAM_BUF_ERR" => PamResultCode::PAM_BUF_ERR,
+            "PAM_PERM_DENIED" => PamResultCode::PAM_PERM_DENIED,
+            "PAM_AUTH_ERR" => PamResultCode::PAM_AUTH_ERR,
+            "PAM_CRED_INSUFFICIENT" => PamResultCode::PAM_CRED_INSUFFICIENT,
+            "PAM_AUTHINFO_UNAVAIL" => PamResultCode::PAM_AUTHINFO_UNAVAIL,
+            "PAM_USER_UNKNOWN" => PamResultCode::PAM_USER_UNKNOWN,
+            "PAM_MAXTRIES" => PamResultCode::PAM_MAXTRIES,
+            "PAM_NEW_AUTHTOK_REQD" => PamResultCode::PAM_NEW_AUTHTOK_REQD,
+            "PAM_ACCT_EXPIRED" => PamResultCode::PAM_ACCT_EXPIRED,
+            "PAM_SESSION_ERR" => PamResultCode::PAM_SESSION_ERR,
+            "PAM_CRED_UNAVAIL" => PamResultCode::PAM_CRED_UNAVAIL,
+            "PAM_CRED_EXPIRED" => PamResultCode::PAM_CRED_EXPIRED,
+            "PAM_CRED_ERR" => PamResultCode::PAM_CRED_ERR,
+            "PAM_NO_MODULE_DATA" => PamResultCode::PAM_NO_MODULE_DATA,
+            "PAM_CONV_ERR" => PamResultCode::PAM_CONV_ERR,
+            "PAM_AUTHTOK_ERR" => PamResultCode::PAM_AUTHTOK_ERR,
+            "PAM_AUTHTOK_RECOVERY_ERR" => PamResultCode::PAM_AUTHTOK_RECOVERY_ERR,
+            "PAM_AUTHTOK_LOCK_BUSY" => PamResultCode::PAM_AUTHTOK_LOCK_BUSY,
+            "PAM_AUTHTOK_DISABLE_AGING" => PamResultCode::PAM_AUTHTOK_DISABLE_AGING,
+            "PAM_TRY_AGAIN" => PamResultCode::PAM_TRY_AGAIN,
+            "PAM_IGNORE" => PamResultCode::PAM_IGNORE,
+            "PAM_ABORT" => PamResultCode::PAM_ABORT,
+            "PAM_AUTHTOK_EXPIRED" => PamResultCode::PAM_AUTHTOK_EXPIRED,
+            "PAM_MODULE_UNKNOWN" => PamResultCode::PAM_MODULE_UNKNOWN,
+            "PAM_BAD_ITEM" => PamResultCode::PAM_BAD_ITEM,
+            "PAM_CONV_AGAIN" => PamResultCode::PAM_CONV_AGAIN,
+            "PAM_INCOMPLETE" => PamResultCode::PAM_INCOMPLETE,
+            _ => return Err(()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names() {
+        assert_eq!("PAM_SUCCESS".parse(), Ok(PamResultCode::PAM_SUCCESS));
+        assert_eq!(
+            "NEW_AUTHTOK_REQD".parse(),
+            Ok(PamResultCode::PAM_NEW_AUTHTOK_REQD)
+        );
+        assert_eq!("PAM_NOPE".parse::<PamResultCode>(), Err(()));
+    }
+}
@@ -0,0 +1,250 @@
+//! The context a hook is invoked with.
+
+use std::cell::OnceCell;
+use std::fmt;
+
+use args::Args;
+use constants::{PamFlag, PamResultCode};
+use constants::{
+    PAM_CHANGE_EXPIRED_AUTHTOK, PAM_DELETE_CRED, PAM_DISALLOW_NULL_AUTHTOK, PAM_ESTABLISH_CRED,
+    PAM_PRELIM_CHECK, PAM_REFRESH_CRED, PAM_REINITIALIZE_CRED, PAM_SILENT, PAM_UPDATE_AUTHTOK,
+};
+use conv::{self, Conv};
+use items::Item;
+use logger::Logger;
+use module::{PamHandle, PamResult};
+
+/// The PAM management groups, as named in the first column of a PAM service
+/// file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    Auth,
+    Account,
+    Session,
+    Password,
+}
+
+/// The `PamHooks` function being invoked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    AcctMgmt,
+    Authenticate,
+    ChAuthTok,
+    CloseSession,
+    OpenSession,
+    SetCred,
+}
+
+impl Hook {
+    /// The management group this hook belongs to.
+    pub fn group(self) -> Group {
+        match self {
+            Hook::Authenticate | Hook::SetCred => Group::Auth,
+            Hook::AcctMgmt => Group::Account,
+            Hook::OpenSession | Hook::CloseSession => Group::Session,
+            Hook::ChAuthTok => Group::Password,
+        }
+    }
+}
+
+/// The flags a hook was invoked with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Flags(PamFlag);
+
+impl Flags {
+    pub fn new(flags: PamFlag) -> Flags {
+        Flags(flags)
+    }
+
+    /// The raw flags, as passed to the `pam_sm_*` function.
+    pub fn bits(self) -> PamFlag {
+        self.0
+    }
+
+    /// Whether all of the bits in `flag` are set.
+    pub fn contains(self, flag: PamFlag) -> bool {
+        self.0 & flag == flag
+    }
+
+    /// `PAM_SILENT`: the module should not generate any messages.
+    pub fn silent(self) -> bool {
+        self.contains(PAM_SILENT)
+    }
+
+    /// `PAM_DISALLOW_NULL_AUTHTOK`: users without a password should not be
+    /// authenticated.
+    pub fn disallow_null_authtok(self) -> bool {
+        self.contains(PAM_DISALLOW_NULL_AUTHTOK)
+    }
+
+    /// `PAM_CHANGE_EXPIRED_AUTHTOK`: only expired passwords should be changed.
+    pub fn change_expired_authtok(self) -> bool {
+        self.contains(PAM_CHANGE_EXPIRED_AUTHTOK)
+    }
+}
+
+impl fmt::Debug for Flags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = [
+            (PAM_SILENT, "PAM_SILENT"),
+            (PAM_DISALLOW_NULL_AUTHTOK, "PAM_DISALLOW_NULL_AUTHTOK"),
+            (PAM_ESTABLISH_CRED, "PAM_ESTABLISH_CRED"),
+            (PAM_DELETE_CRED, "PAM_DELETE_CRED"),
+            (PAM_REINITIALIZE_CRED, "PAM_REINITIALIZE_CRED"),
+            (PAM_REFRESH_CRED, "PAM_REFRESH_CRED"),
+            (PAM_CHANGE_EXPIRED_AUTHTOK, "PAM_CHANGE_EXPIRED_AUTHTOK"),
+            (PAM_PRELIM_CHECK, "PAM_PRELIM_CHECK"),
+            (PAM_UPDATE_AUTHTOK, "PAM_UPDATE_AUTHTOK"),
+        ];
+        let set: Vec<_> = names
+            .iter()
+            .filter(|&&(flag, _)| self.contains(flag))
+            .map(|&(_, name)| name)
+            .collect();
+        write!(f, "Flags({:#x}: {})", self.0, set.join(" | "))
+    }
+}
+
+/// Everything a hook is invoked with, passed to each `PamHooks` function.
+///
+/// Besides the handle, arguments and flags of the call, the context provides
+/// the conversation (fetched from PAM the first time it is needed) and a
+/// logger that identifies the service and user in each message.
+pub struct HookContext<'a> {
+    pamh: &'a mut PamHandle,
+    args: Args<'a>,
+    flags: Flags,
+    hook: Hook,
+    conv: OnceCell<Option<*const conv::Inner>>,
+}
+
+impl<'a> HookContext<'a> {
+    pub fn new(pamh: &'a mut PamHandle, args: Args<'a>, flags: PamFlag, hook: Hook) -> Self {
+        HookContext {
+            pamh,
+            args,
+            flags: Flags::new(flags),
+            hook,
+            conv: OnceCell::new(),
+        }
+    }
+
+    pub fn handle(&self) -> &PamHandle {
+        self.pamh
+    }
+
+    /// The handle, for calls that modify it such as `set_item_str`.
+    pub fn handle_mut(&mut self) -> &mut PamHandle {
+        // The application's conversation may be replaced
+        self.conv = OnceCell::new();
+        self.pamh
+    }
+
+    /// The arguments the module was configured with.
+    pub fn args(&self) -> Args<'a> {
+        self.args.clone()
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    pub fn hook(&self) -> Hook {
+        self.hook
+    }
+
+    /// The management group being invoked.
+    pub fn group(&self) -> Group {
+        self.hook.group()
+    }
+
+    /// The application's conversation, honoring `PAM_SILENT`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation can't be retrieved, or
+    /// `PAM_CONV_ERR` if the application didn't provide one.
+    pub fn conv(&self) -> PamResult<Conv<'_>> {
+        let raw = match self.conv.get() {
+            Some(&raw) => raw,
+            None => {
+                let raw = self.pamh.get_item::<Conv>()?.map(Item::into_raw);
+                let _ = self.conv.set(raw);
+                raw
+            }
+        };
+        match raw {
+            // The pointer came from `get_item` and the handle hasn't been
+            // modified since
+            Some(raw) => Ok(unsafe { Conv::from_raw(raw) }.with_flags(self.flags.bits())),
+            None => Err(PamResultCode::PAM_CONV_ERR),
+        }
+    }
+
+    /// The name of the user, prompting for it if it isn't known yet.
+    ///
+    /// # Errors
+    ///
+    /// See `PamHandle::get_user`.
+    pub fn user(&self) -> PamResult<String> {
+        self.pamh.get_user(None)
+    }
+
+    /// Runs `future` to completion on the module's shared tokio runtime.
+    ///
+    /// If the module was given a `deadline=<seconds>` argument, the future is
+    /// abandoned once that much time has passed. See `pam::runtime`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PAM_AUTHINFO_UNAVAIL` if the deadline elapses, or
+    /// `PAM_SYSTEM_ERR` if the runtime can't be built.
+    #[cfg(feature = "tokio")]
+    pub fn block_on<F: std::future::Future>(&self, future: F) -> PamResult<F::Output> {
+        let deadline = self.args.get("deadline").and_then(|secs| {
+            let deadline = crate::args::parse_secs(secs);
+            if deadline.is_none() {
+                self.log()
+                    .warn(&format!("ignoring invalid deadline={}", secs));
+            }
+            deadline
+        });
+        crate::runtime::block_on_with_deadline(deadline, future)
+    }
+
+    /// A logger that tags each message with the service and user.
+    ///
+    /// Debug messages are only written when the module was given the `debug`
+    /// argument.
+    pub fn log(&self) -> Logger<'_> {
+        let item = |item: PamResult<Option<String>>| match item {
+            Ok(Some(value)) => value,
+            _ => "?".to_owned(),
+        };
+        let tag = format!(
+            "service={} user={}",
+            item(self.pamh.service()),
+            item(self.pamh.user())
+        );
+        Logger::new(self.pamh)
+            .tagged(tag)
+            .with_debug(self.args.has_flag("debug"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags() {
+        let flags = Flags::new(PAM_SILENT | PAM_DISALLOW_NULL_AUTHTOK);
+        assert!(flags.silent());
+        assert!(flags.disallow_null_authtok());
+        assert!(!flags.change_expired_authtok());
+        assert_eq!(
+            format!("{:?}", flags),
+            "Flags(0x8001: PAM_SILENT | PAM_DISALLOW_NULL_AUTHTOK)"
+        );
+    }
+}
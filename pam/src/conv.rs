@@ -1,11 +1,17 @@
 use libc::{c_char, c_int};
-use std::ffi::{CStr, CString};
+use std::convert::TryFrom;
+use std::ffi::CStr;
 use std::ptr;
 
 use constants::PamResultCode;
-use constants::PamMessageStyle;
+use constants::{PamFlag, PamMessageStyle, PAM_SILENT};
+use constants::{
+    PAM_BINARY_PROMPT, PAM_ERROR_MSG, PAM_PROMPT_ECHO_OFF, PAM_PROMPT_ECHO_ON, PAM_RADIO_TYPE,
+    PAM_TEXT_INFO,
+};
 use items::Item;
-use module::PamResult;
+use module::{to_cstring, PamResult};
+use secret::{wipe, Secret};
 
 #[repr(C)]
 struct PamMessage {
@@ -19,6 +25,80 @@ struct PamResponse {
     resp_retcode: libc::c_int, // Unused - always zero
 }
 
+/// A `PAM_BINARY_PROMPT` packet.
+///
+/// Packets are framed as by Linux-PAM's `libpamc`: a 4-byte big-endian length
+/// that includes the 5-byte header, a control byte (see the `PAM_BPC_*`
+/// constants), then the data. Applications implementing the binary protocol
+/// can use `from_bytes` and `to_bytes` to handle the other side of
+/// `Conv::send_binary`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryPacket {
+    pub control: u8,
+    pub data: Vec<u8>,
+}
+
+impl BinaryPacket {
+    const HEADER_LEN: usize = 5;
+
+    pub fn new(control: u8, data: &[u8]) -> BinaryPacket {
+        BinaryPacket {
+            control,
+            data: data.to_vec(),
+        }
+    }
+
+    /// Encodes this packet with its length and control byte header.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PAM_BUF_ERR` if the packet is too long for its length field.
+    pub fn to_bytes(&self) -> PamResult<Vec<u8>> {
+        let len = Self::HEADER_LEN
+            .checked_add(self.data.len())
+            .and_then(|len| u32::try_from(len).ok())
+            .ok_or(PamResultCode::PAM_BUF_ERR)?;
+        let mut bytes = Vec::with_capacity(len as usize);
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.push(self.control);
+        bytes.extend_from_slice(&self.data);
+        Ok(bytes)
+    }
+
+    /// Decodes a packet, ignoring anything past its declared length.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PAM_CONV_ERR` if `bytes` does not hold a complete packet.
+    pub fn from_bytes(bytes: &[u8]) -> PamResult<BinaryPacket> {
+        let (len, control) = Self::header(bytes).ok_or(PamResultCode::PAM_CONV_ERR)?;
+        let data = bytes
+            .get(Self::HEADER_LEN..len)
+            .ok_or(PamResultCode::PAM_CONV_ERR)?;
+        Ok(BinaryPacket::new(control, data))
+    }
+
+    /// Decodes a packet from a pointer to its header, as found in a
+    /// conversation response.
+    unsafe fn from_ptr(ptr: *const u8) -> PamResult<BinaryPacket> {
+        let header = std::slice::from_raw_parts(ptr, Self::HEADER_LEN);
+        match Self::header(header) {
+            Some((len, _)) if len >= Self::HEADER_LEN => {
+                Self::from_bytes(std::slice::from_raw_parts(ptr, len))
+            }
+            _ => Err(PamResultCode::PAM_CONV_ERR),
+        }
+    }
+
+    /// Splits the packet header into its total length and control byte.
+    fn header(bytes: &[u8]) -> Option<(usize, u8)> {
+        match *bytes {
+            [a, b, c, d, control, ..] => Some((u32::from_be_bytes([a, b, c, d]) as usize, control)),
+            _ => None,
+        }
+    }
+}
+
 /// `PamConv` acts as a channel for communicating with user.
 ///
 /// Communication is mediated by the pam client (the application that invoked
@@ -35,9 +115,23 @@ pub struct Inner {
     appdata_ptr: *const libc::c_void,
 }
 
-pub struct Conv<'a>(&'a Inner);
+pub struct Conv<'a> {
+    inner: &'a Inner,
+    silent: bool,
+}
 
 impl<'a> Conv<'a> {
+    /// Applies the flags a hook was invoked with.
+    ///
+    /// When `PAM_SILENT` is set, `info` and `error` messages are dropped
+    /// instead of being sent to the application. Prompts are always sent.
+    pub fn with_flags(self, flags: PamFlag) -> Self {
+        Conv {
+            silent: flags & PAM_SILENT != 0,
+            ..self
+        }
+    }
+
     /// Sends a message to the pam client.
     ///
     /// This will typically result in the user seeing a message or a prompt.
@@ -53,17 +147,27 @@ impl<'a> Conv<'a> {
     /// Note that the user experience will depend on how the client implements
     /// these message styles - and not all applications implement all message
     /// styles.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation function fails, or `PAM_CONV_ERR`
+    /// if the message contains a nul byte.
     pub fn send(&self, style: PamMessageStyle, msg: &str) -> PamResult<Option<&CStr>> {
         let mut resp_ptr: *const PamResponse = ptr::null();
-        let msg_cstr = CString::new(msg).unwrap();
+        let msg_cstr = to_cstring(msg, PamResultCode::PAM_CONV_ERR)?;
         let msg = PamMessage {
             msg_style: style,
             msg: msg_cstr.as_ptr(),
         };
 
-        let ret = (self.0.conv)(1, &&msg, &mut resp_ptr, self.0.appdata_ptr);
+        let ret = (self.inner.conv)(1, &&msg, &mut resp_ptr, self.inner.appdata_ptr);
 
         if PamResultCode::PAM_SUCCESS == ret {
+            // Some applications don't allocate a response at all for styles
+            // that don't return user input like PAM_TEXT_INFO
+            if resp_ptr.is_null() {
+                return Ok(None);
+            }
             // PamResponse.resp is null for styles that don't return user input like PAM_TEXT_INFO
             let response = unsafe { (*resp_ptr).resp };
             if response.is_null() {
@@ -75,9 +179,203 @@ impl<'a> Conv<'a> {
             Err(ret)
         }
     }
+
+    /// Sends a `PAM_BINARY_PROMPT` packet to the pam client and returns the
+    /// packet it replies with.
+    ///
+    /// Only applications (or agents) that implement the Linux-PAM binary
+    /// prompt protocol will understand these; others will typically fail the
+    /// conversation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation function fails, `PAM_BUF_ERR` if
+    /// `data` is too long to frame, or `PAM_CONV_ERR` if the application
+    /// doesn't reply with a well-formed packet.
+    pub fn send_binary(&self, control: u8, data: &[u8]) -> PamResult<BinaryPacket> {
+        let mut resp_ptr: *const PamResponse = ptr::null();
+        let packet = BinaryPacket::new(control, data).to_bytes()?;
+        let msg = PamMessage {
+            msg_style: PAM_BINARY_PROMPT,
+            msg: packet.as_ptr().cast(),
+        };
+
+        let ret = (self.inner.conv)(1, &&msg, &mut resp_ptr, self.inner.appdata_ptr);
+        if PamResultCode::PAM_SUCCESS != ret {
+            return Err(ret);
+        }
+        if resp_ptr.is_null() {
+            return Err(PamResultCode::PAM_CONV_ERR);
+        }
+
+        // The response is ours to free once we've copied the reply out of it
+        unsafe {
+            let response = (*resp_ptr).resp;
+            let reply = if response.is_null() {
+                Err(PamResultCode::PAM_CONV_ERR)
+            } else {
+                BinaryPacket::from_ptr(response.cast())
+            };
+            libc::free(response as *mut libc::c_void);
+            libc::free(resp_ptr as *mut libc::c_void);
+            reply
+        }
+    }
+
+    /// Prompts the user for input that should not be echoed, such as a password.
+    ///
+    /// The application's copy of the response is wiped and freed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails, or `PAM_CONV_ERR` if the
+    /// application returned no response.
+    pub fn prompt_hidden(&self, msg: &str) -> PamResult<Secret> {
+        self.respond(PAM_PROMPT_ECHO_OFF, msg)?
+            .ok_or(PamResultCode::PAM_CONV_ERR)
+    }
+
+    /// Prompts the user for input that may be echoed, such as a username.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails, or `PAM_CONV_ERR` if the
+    /// application returned no response or one that is not valid UTF-8.
+    pub fn prompt_visible(&self, msg: &str) -> PamResult<String> {
+        self.prompt(PAM_PROMPT_ECHO_ON, msg)
+    }
+
+    /// Shows an informational message to the user, unless `PAM_SILENT` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails.
+    pub fn info(&self, msg: &str) -> PamResult<()> {
+        self.message(PAM_TEXT_INFO, msg)
+    }
+
+    /// Shows an error message to the user, unless `PAM_SILENT` is set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the conversation fails.
+    pub fn error(&self, msg: &str) -> PamResult<()> {
+        self.message(PAM_ERROR_MSG, msg)
+    }
+
+    /// Asks the user a yes/no question.
+    ///
+    /// The question is sent as `PAM_RADIO_TYPE`. Applications that don't
+    /// support that style get a `PAM_PROMPT_ECHO_ON` prompt with a `[y/N]`
+    /// hint instead: those that fail the conversation with `PAM_CONV_ERR`,
+    /// or that return no response, as for a message. Anything other than
+    /// "y" or "yes" is taken as a no.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PAM_CONV_ERR` if the message contains a nul byte, otherwise
+    /// see `prompt_hidden`.
+    pub fn confirm(&self, msg: &str) -> PamResult<bool> {
+        if msg.contains('\0') {
+            return Err(PamResultCode::PAM_CONV_ERR);
+        }
+        let answer = match self.respond(PAM_RADIO_TYPE, msg) {
+            Ok(Some(answer)) => answer
+                .expose_str()
+                .map(str::to_owned)
+                .ok_or(PamResultCode::PAM_CONV_ERR)?,
+            Ok(None) | Err(PamResultCode::PAM_CONV_ERR) => {
+                self.prompt(PAM_PROMPT_ECHO_ON, &format!("{} [y/N] ", msg))?
+            }
+            Err(err) => return Err(err),
+        };
+        let answer = answer.trim();
+        Ok(answer.eq_ignore_ascii_case("y") || answer.eq_ignore_ascii_case("yes"))
+    }
+
+    /// Asks the user to pick one of `options` from a numbered menu, returning
+    /// the index of the chosen option.
+    ///
+    /// The user may answer with either the number or the text of an option.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PAM_CONV_ERR` if `options` is empty or the answer doesn't
+    /// match any option, otherwise see `prompt_hidden`.
+    pub fn choose(&self, msg: &str, options: &[&str]) -> PamResult<usize> {
+        if options.is_empty() {
+            return Err(PamResultCode::PAM_CONV_ERR);
+        }
+        let mut menu = String::new();
+        for (i, option) in options.iter().enumerate() {
+            menu.push_str(&format!("{}) {}\n", i + 1, option));
+        }
+        menu.push_str(msg);
+
+        let answer = self.prompt(PAM_PROMPT_ECHO_ON, &menu)?;
+        let answer = answer.trim();
+        match answer.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= options.len() => Ok(n - 1),
+            _ => options
+                .iter()
+                .position(|option| *option == answer)
+                .ok_or(PamResultCode::PAM_CONV_ERR),
+        }
+    }
+
+    fn prompt(&self, style: PamMessageStyle, msg: &str) -> PamResult<String> {
+        match self.respond(style, msg)? {
+            Some(resp) => resp
+                .expose_str()
+                .map(str::to_owned)
+                .ok_or(PamResultCode::PAM_CONV_ERR),
+            None => Err(PamResultCode::PAM_CONV_ERR),
+        }
+    }
+
+    fn message(&self, style: PamMessageStyle, msg: &str) -> PamResult<()> {
+        if self.silent {
+            return Ok(());
+        }
+        self.respond(style, msg).map(|_| ())
+    }
+
+    /// Like `send`, but takes ownership of the response: it is copied into a
+    /// `Secret`, and the application's copy is wiped and freed.
+    fn respond(&self, style: PamMessageStyle, msg: &str) -> PamResult<Option<Secret>> {
+        let mut resp_ptr: *const PamResponse = ptr::null();
+        let msg_cstr = to_cstring(msg, PamResultCode::PAM_CONV_ERR)?;
+        let msg = PamMessage {
+            msg_style: style,
+            msg: msg_cstr.as_ptr(),
+        };
+
+        let ret = (self.inner.conv)(1, &&msg, &mut resp_ptr, self.inner.appdata_ptr);
+        if PamResultCode::PAM_SUCCESS != ret {
+            return Err(ret);
+        }
+        if resp_ptr.is_null() {
+            return Ok(None);
+        }
+        unsafe {
+            let response = (*resp_ptr).resp as *mut c_char;
+            let secret = if response.is_null() {
+                None
+            } else {
+                let len = libc::strlen(response);
+                let buf = std::slice::from_raw_parts_mut(response.cast::<u8>(), len);
+                let secret = Secret::from_bytes(buf);
+                wipe(buf);
+                Some(secret)
+            };
+            libc::free(response.cast());
+            libc::free(resp_ptr as *mut libc::c_void);
+            Ok(secret)
+        }
+    }
 }
 
-impl<'a> Item for Conv<'a> {
+impl<'a> Item<'a> for Conv<'a> {
     type Raw = Inner;
 
     fn type_id() -> crate::items::ItemType {
@@ -85,10 +383,246 @@ impl<'a> Item for Conv<'a> {
     }
 
     unsafe fn from_raw(raw: *const Self::Raw) -> Self {
-        Self(&*raw)
+        Conv {
+            inner: &*raw,
+            silent: false,
+        }
     }
 
     fn into_raw(self) -> *const Self::Raw {
-        self.0 as _
+        self.inner as _
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use constants::{PAM_BPC_DONE, PAM_BPC_SELECT};
+    use std::cell::RefCell;
+
+    /// A scripted application: answers the prompts with `answers` in turn,
+    /// repeating the last one, and records what it was sent.
+    pub(crate) struct Script {
+        answers: Vec<&'static str>,
+        refuse_radio: bool,
+        fail_with: Option<PamResultCode>,
+        seen: RefCell<Vec<(PamMessageStyle, String)>>,
+    }
+
+    impl Script {
+        pub(crate) fn new(answer: &'static str) -> Script {
+            Script::answering(&[answer])
+        }
+
+        pub(crate) fn answering(answers: &[&'static str]) -> Script {
+            Script {
+                answers: answers.to_vec(),
+                refuse_radio: false,
+                fail_with: None,
+                seen: RefCell::new(Vec::new()),
+            }
+        }
+
+        pub(crate) fn inner(&self) -> Inner {
+            Inner {
+                conv: scripted,
+                appdata_ptr: (self as *const Script).cast(),
+            }
+        }
+
+        pub(crate) fn seen(&self) -> Vec<(PamMessageStyle, String)> {
+            self.seen.borrow().clone()
+        }
+    }
+
+    extern "C" fn scripted(
+        num_msg: c_int,
+        pam_message: &&PamMessage,
+        pam_response: &mut *const PamResponse,
+        appdata_ptr: *const libc::c_void,
+    ) -> PamResultCode {
+        assert_eq!(num_msg, 1);
+        let script = unsafe { &*appdata_ptr.cast::<Script>() };
+        let msg = *pam_message;
+        if msg.msg_style == PAM_BINARY_PROMPT {
+            // Reply to binary prompts by echoing the packet's data back
+            let packet = unsafe { BinaryPacket::from_ptr(msg.msg.cast()) }.unwrap();
+            let reply = BinaryPacket::new(PAM_BPC_DONE, &packet.data)
+                .to_bytes()
+                .unwrap();
+            unsafe {
+                let resp =
+                    libc::calloc(1, std::mem::size_of::<PamResponse>()).cast::<PamResponse>();
+                let buf = libc::malloc(reply.len()).cast::<u8>();
+                ptr::copy_nonoverlapping(reply.as_ptr(), buf, reply.len());
+                (*resp).resp = buf.cast();
+                *pam_response = resp;
+            }
+            return PamResultCode::PAM_SUCCESS;
+        }
+        let text = unsafe { CStr::from_ptr(msg.msg) };
+        script
+            .seen
+            .borrow_mut()
+            .push((msg.msg_style, text.to_string_lossy().into_owned()));
+        if script.refuse_radio && msg.msg_style == PAM_RADIO_TYPE {
+            return PamResultCode::PAM_CONV_ERR;
+        }
+        if let Some(err) = script.fail_with {
+            return err;
+        }
+        let prompts = script
+            .seen
+            .borrow()
+            .iter()
+            .filter(|&&(style, _)| style != PAM_TEXT_INFO && style != PAM_ERROR_MSG)
+            .count();
+        let answer = script.answers[prompts.saturating_sub(1).min(script.answers.len() - 1)];
+        unsafe {
+            let resp = libc::calloc(1, std::mem::size_of::<PamResponse>()).cast::<PamResponse>();
+            if msg.msg_style != PAM_TEXT_INFO && msg.msg_style != PAM_ERROR_MSG {
+                let answer = std::ffi::CString::new(answer).unwrap();
+                (*resp).resp = libc::strdup(answer.as_ptr());
+            }
+            *pam_response = resp;
+        }
+        PamResultCode::PAM_SUCCESS
+    }
+
+    #[test]
+    fn send_relays_responses() {
+        let script = Script::new("pong");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        let resp = conv.send(PAM_PROMPT_ECHO_ON, "ping").unwrap();
+        assert_eq!(resp.map(CStr::to_bytes), Some(&b"pong"[..]));
+        assert_eq!(conv.send(PAM_TEXT_INFO, "hello").unwrap(), None);
+    }
+
+    #[test]
+    fn send_rejects_nul_bytes() {
+        let script = Script::new("pong");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(
+            conv.send(PAM_PROMPT_ECHO_ON, "pi\0ng"),
+            Err(PamResultCode::PAM_CONV_ERR)
+        );
+        assert!(script.seen().is_empty());
+    }
+
+    #[test]
+    fn silent_drops_messages_but_not_prompts() {
+        let script = Script::new("secret");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) }.with_flags(PAM_SILENT);
+        conv.info("hello").unwrap();
+        conv.error("oops").unwrap();
+        assert_eq!(
+            conv.prompt_hidden("Password: ").unwrap(),
+            Secret::from_bytes(b"secret")
+        );
+        assert_eq!(
+            script.seen(),
+            vec![(PAM_PROMPT_ECHO_OFF, "Password: ".to_string())]
+        );
+    }
+
+    #[test]
+    fn confirm_falls_back_to_echo_on() {
+        let mut script = Script::new("Yes");
+        script.refuse_radio = true;
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert!(conv.confirm("Continue?").unwrap());
+        assert_eq!(
+            script.seen(),
+            vec![
+                (PAM_RADIO_TYPE, "Continue?".to_string()),
+                (PAM_PROMPT_ECHO_ON, "Continue? [y/N] ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn confirm_only_falls_back_when_radio_is_refused() {
+        let script = Script::new("no");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert!(!conv.confirm("Continue?").unwrap());
+        assert_eq!(
+            script.seen(),
+            vec![(PAM_RADIO_TYPE, "Continue?".to_string())]
+        );
+
+        let mut script = Script::new("");
+        script.fail_with = Some(PamResultCode::PAM_ABORT);
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(conv.confirm("Continue?"), Err(PamResultCode::PAM_ABORT));
+        assert_eq!(script.seen().len(), 1);
+
+        let script = Script::new("yes");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(
+            conv.confirm("Conti\0nue?"),
+            Err(PamResultCode::PAM_CONV_ERR)
+        );
+        assert!(script.seen().is_empty());
+    }
+
+    #[test]
+    fn choose_accepts_number_or_text() {
+        let options = ["sms", "push"];
+
+        let script = Script::new("2");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(conv.choose("Method: ", &options), Ok(1));
+        assert_eq!(
+            script.seen(),
+            vec![(PAM_PROMPT_ECHO_ON, "1) sms\n2) push\nMethod: ".to_string())]
+        );
+
+        let script = Script::new("sms");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(conv.choose("Method: ", &options), Ok(0));
+
+        let script = Script::new("3");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(
+            conv.choose("Method: ", &options),
+            Err(PamResultCode::PAM_CONV_ERR)
+        );
+    }
+
+    #[test]
+    fn binary_packets_round_trip() {
+        let packet = BinaryPacket::new(PAM_BPC_SELECT, b"agent/1.0");
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(&bytes[..5], &[0, 0, 0, 14, PAM_BPC_SELECT]);
+        assert_eq!(BinaryPacket::from_bytes(&bytes), Ok(packet));
+        assert_eq!(
+            BinaryPacket::from_bytes(&bytes[..10]),
+            Err(PamResultCode::PAM_CONV_ERR)
+        );
+        assert_eq!(
+            BinaryPacket::from_bytes(&[0, 0, 0, 1, PAM_BPC_SELECT]),
+            Err(PamResultCode::PAM_CONV_ERR)
+        );
+    }
+
+    #[test]
+    fn send_binary_returns_reply_packet() {
+        let script = Script::new("");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(
+            conv.send_binary(PAM_BPC_SELECT, b"\0challenge\0"),
+            Ok(BinaryPacket::new(PAM_BPC_DONE, b"\0challenge\0"))
+        );
     }
 }
@@ -0,0 +1,137 @@
+//! Module-global state that survives `fork()`.
+//!
+//! Applications like sshd and login fork between the auth and session
+//! phases, so a connection pool or background thread created in
+//! `sm_authenticate` may be unusable by the time `sm_open_session` runs in the
+//! child: its threads only exist in the parent. `ForkSafe` remembers which
+//! process created its value and creates a new one when used from any other.
+
+use std::convert::Infallible;
+use std::process;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A lazily created value that is recreated after the process forks.
+///
+/// ```
+/// use pam::fork::ForkSafe;
+/// use std::collections::HashMap;
+///
+/// static CACHE: ForkSafe<HashMap<String, String>> = ForkSafe::new();
+///
+/// let cache = CACHE.get_or_init(HashMap::new);
+/// ```
+///
+/// A value left behind by the parent process is leaked rather than dropped,
+/// as its destructor may try to stop threads that don't exist in the child.
+///
+/// `init` runs without the lock held, so it may use the same `ForkSafe`, and
+/// a slow `init` doesn't hold up other threads reading an existing value.
+/// Threads that find no value at the same time may each run `init`, in which
+/// case the first value stored wins and the others are dropped. The lock is
+/// only held briefly, but a process that forks while another thread holds it
+/// leaves it locked in the child, as with any `std::sync::Mutex`.
+pub struct ForkSafe<T> {
+    value: Mutex<Option<(u32, Arc<T>)>>,
+}
+
+impl<T> ForkSafe<T> {
+    pub const fn new() -> ForkSafe<T> {
+        ForkSafe {
+            value: Mutex::new(None),
+        }
+    }
+
+    /// The value for the current process, creating it with `init` if this
+    /// process has none yet.
+    pub fn get_or_init<F: FnOnce() -> T>(&self, init: F) -> Arc<T> {
+        match self.get_or_try_init(|| Ok::<T, Infallible>(init())) {
+            Ok(value) => value,
+            Err(never) => match never {},
+        }
+    }
+
+    /// The value for the current process, creating it with `init` if this
+    /// process has none yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from `init`, in which case it will be called again
+    /// on the next use.
+    pub fn get_or_try_init<E, F: FnOnce() -> Result<T, E>>(&self, init: F) -> Result<Arc<T>, E> {
+        self.get_or_try_init_in(process::id(), init)
+    }
+
+    fn get_or_try_init_in<E, F>(&self, pid: u32, init: F) -> Result<Arc<T>, E>
+    where
+        F: FnOnce() -> Result<T, E>,
+    {
+        if let Some(value) = self.current(pid) {
+            return Ok(value);
+        }
+        let value = Arc::new(init()?);
+        let mut guard = self.lock();
+        match *guard {
+            // Another thread, or `init` itself, got there first
+            Some((owner, ref current)) if owner == pid => return Ok(current.clone()),
+            _ => {}
+        }
+        if let Some((_, stale)) = guard.replace((pid, value.clone())) {
+            std::mem::forget(stale);
+        }
+        Ok(value)
+    }
+
+    /// The value, if it belongs to the process `pid`.
+    fn current(&self, pid: u32) -> Option<Arc<T>> {
+        match *self.lock() {
+            Some((owner, ref value)) if owner == pid => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<(u32, Arc<T>)>> {
+        self.value.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Default for ForkSafe<T> {
+    fn default() -> ForkSafe<T> {
+        ForkSafe::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn recreates_value_in_new_process() {
+        let cell = ForkSafe::new();
+        let inits = Cell::new(0);
+        let init = || -> Result<u32, ()> {
+            inits.set(inits.get() + 1);
+            Ok(inits.get())
+        };
+
+        assert_eq!(*cell.get_or_try_init_in(100, init).unwrap(), 1);
+        assert_eq!(*cell.get_or_try_init_in(100, init).unwrap(), 1);
+        assert_eq!(*cell.get_or_try_init_in(101, init).unwrap(), 2);
+        assert_eq!(inits.get(), 2);
+    }
+
+    #[test]
+    fn init_may_use_the_same_cell() {
+        let cell = ForkSafe::new();
+        let outer = cell.get_or_init(|| *cell.get_or_init(|| 1) + 1);
+        assert!(Arc::ptr_eq(&outer, &cell.get_or_init(|| 3)));
+        assert_eq!(*outer, 1);
+    }
+
+    #[test]
+    fn retries_failed_init() {
+        let cell = ForkSafe::new();
+        assert_eq!(cell.get_or_try_init(|| Err("down")), Err("down"));
+        assert_eq!(*cell.get_or_init(|| 7), 7);
+    }
+}
@@ -28,8 +28,12 @@ pub enum ItemType {
     AuthTokType = 13,
 }
 
-// A type that can be requested by `pam::Handle::get_item`.
-pub trait Item {
+/// A type that can be requested by `pam::Handle::get_item`.
+///
+/// The lifetime `'a` is that of the borrow of the `PamHandle` the item was
+/// read from: PAM owns the underlying memory and may free it on the next
+/// `pam_set_item`, so an item must not outlive that borrow.
+pub trait Item<'a> {
     /// The `repr(C)` type that is returned (by pointer) by the underlying `pam_get_item` function.
     type Raw;
 
@@ -47,11 +51,35 @@ pub trait Item {
     fn into_raw(self) -> *const Self::Raw;
 }
 
+/// An item whose value is a nul-terminated string.
+///
+/// Implemented by every string item, this is what the owned accessors such as
+/// `PamHandle::get_item_string` build on.
+pub trait StringItem<'a>: Item<'a> {
+    /// The borrowed value of this item.
+    fn as_cstr(&self) -> &'a std::ffi::CStr;
+}
+
 macro_rules! cstr_item {
     ($name:ident) => {
         #[derive(Debug)]
         pub struct $name<'s>(pub &'s std::ffi::CStr);
 
+        cstr_item!(@impl $name);
+    };
+    // Items holding authentication tokens, which must not end up in logs
+    ($name:ident, secret) => {
+        pub struct $name<'s>(pub &'s std::ffi::CStr);
+
+        impl<'s> std::fmt::Debug for $name<'s> {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                write!(f, "{}(<redacted>)", stringify!($name))
+            }
+        }
+
+        cstr_item!(@impl $name);
+    };
+    (@impl $name:ident) => {
         impl<'s> std::ops::Deref for $name<'s> {
             type Target = &'s std::ffi::CStr;
             fn deref(&self) -> &Self::Target {
@@ -59,7 +87,13 @@ macro_rules! cstr_item {
             }
         }
 
-        impl<'s> Item for $name<'s> {
+        impl<'s> StringItem<'s> for $name<'s> {
+            fn as_cstr(&self) -> &'s std::ffi::CStr {
+                self.0
+            }
+        }
+
+        impl<'s> Item<'s> for $name<'s> {
             type Raw = libc::c_char;
 
             fn type_id() -> ItemType {
@@ -82,7 +116,7 @@ cstr_item!(User);
 cstr_item!(Tty);
 cstr_item!(RHost);
 // Conv
-cstr_item!(AuthTok);
-cstr_item!(OldAuthTok);
+cstr_item!(AuthTok, secret);
+cstr_item!(OldAuthTok, secret);
 cstr_item!(RUser);
 cstr_item!(UserPrompt);
@@ -24,11 +24,43 @@
 //! a Linux system.  That means that it might take some work to get this library
 //! to work on other platforms.
 
+// Modules call into this crate from `extern "C"` hooks, so nothing in it may
+// unwind across the FFI boundary into the PAM stack.
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::expect_used,
+        clippy::indexing_slicing,
+        clippy::panic,
+        clippy::todo,
+        clippy::unimplemented,
+        clippy::unreachable,
+        clippy::unwrap_used
+    )
+)]
+
 extern crate libc;
+#[cfg(feature = "macros")]
+extern crate pam_macros;
+#[cfg(feature = "tokio")]
+extern crate tokio;
 
+pub mod args;
 pub mod constants;
+pub mod context;
 pub mod conv;
+pub mod fork;
 pub mod items;
+pub mod logger;
 #[doc(hidden)]
 pub mod macros;
 pub mod module;
+pub mod password;
+pub mod policy;
+#[cfg(feature = "tokio")]
+pub mod runtime;
+pub mod secret;
+pub mod session;
+
+#[cfg(feature = "macros")]
+pub use pam_macros::pam_module;
@@ -0,0 +1,106 @@
+//! Logging to the system log on behalf of a pam module.
+//!
+//! Messages are written with `pam_syslog`, which prefixes them with the module
+//! and service names, so they end up alongside those of the other modules in
+//! the stack (typically in the `auth` facility).
+
+use libc::{c_char, c_int};
+use std::ffi::CString;
+
+use module::PamHandle;
+
+#[link(name = "pam")]
+extern "C" {
+    fn pam_syslog(pamh: *const PamHandle, priority: c_int, fmt: *const c_char, ...);
+}
+
+/// The severity of a log message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn priority(self) -> c_int {
+        match self {
+            Level::Error => libc::LOG_ERR,
+            Level::Warning => libc::LOG_WARNING,
+            Level::Info => libc::LOG_INFO,
+            Level::Debug => libc::LOG_DEBUG,
+        }
+    }
+}
+
+/// Writes messages to the system log through a pam handle.
+pub struct Logger<'a> {
+    pamh: &'a PamHandle,
+    tag: Option<String>,
+    debug: bool,
+}
+
+impl<'a> Logger<'a> {
+    pub fn new(pamh: &'a PamHandle) -> Logger<'a> {
+        Logger {
+            pamh,
+            tag: None,
+            debug: true,
+        }
+    }
+
+    /// Prefixes every message with `tag`, e.g. to identify the user a message
+    /// is about.
+    pub fn tagged(self, tag: String) -> Logger<'a> {
+        Logger {
+            tag: Some(tag),
+            ..self
+        }
+    }
+
+    /// Enables or disables `Level::Debug` messages, which are written by
+    /// default.
+    pub fn with_debug(self, debug: bool) -> Logger<'a> {
+        Logger { debug, ..self }
+    }
+
+    /// Logs `msg` at the given level.
+    ///
+    /// Logging never fails: nul bytes in `msg` are replaced so that the rest
+    /// of the message is still written.
+    pub fn log(&self, level: Level, msg: &str) {
+        if level == Level::Debug && !self.debug {
+            return;
+        }
+        let msg = match self.tag {
+            Some(ref tag) => format!("[{}] {}", tag, msg),
+            None => msg.to_owned(),
+        };
+        let msg = CString::new(msg.replace('\0', "\\0")).unwrap_or_default();
+        unsafe {
+            pam_syslog(
+                self.pamh,
+                level.priority(),
+                b"%s\0".as_ptr().cast(),
+                msg.as_ptr(),
+            );
+        }
+    }
+
+    pub fn error(&self, msg: &str) {
+        self.log(Level::Error, msg)
+    }
+
+    pub fn warn(&self, msg: &str) {
+        self.log(Level::Warning, msg)
+    }
+
+    pub fn info(&self, msg: &str) {
+        self.log(Level::Info, msg)
+    }
+
+    pub fn debug(&self, msg: &str) {
+        self.log(Level::Debug, msg)
+    }
+}
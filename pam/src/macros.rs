@@ -1,7 +1,28 @@
+use libc::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+
+use args::Args;
+use constants::{PamFlag, PamResultCode};
+use context::{Hook, HookContext};
+use logger::Logger;
+use module::PamHandle;
+
 /// Macro to generate the `extern "C"` entrypoint bindings needed by PAM
 ///
 /// You can call `pam_hooks!(SomeType);` for any type that implements `PamHooks`
 ///
+/// By default the entrypoints of all four management groups are exported, and
+/// the hooks a module doesn't implement return `PAM_IGNORE`. To export only
+/// some groups, list them after the type:
+///
+/// - `auth`: `pam_sm_authenticate` and `pam_sm_setcred`
+/// - `account`: `pam_sm_acct_mgmt`
+/// - `session`: `pam_sm_open_session` and `pam_sm_close_session`
+/// - `password`: `pam_sm_chauthtok`
+///
+/// PAM then treats the module like any other that lacks those functions,
+/// failing calls for the missing groups with `PAM_MODULE_UNKNOWN`.
+///
 /// ## Examples:
 ///
 /// Here is full example of a PAM module that would authenticate and authorize everybody:
@@ -9,21 +30,21 @@
 /// ```
 /// #[macro_use] extern crate pam;
 ///
-/// use pam::module::{PamHooks, PamHandle};
-/// use pam::constants::{PamResultCode, PamFlag};
-/// use std::ffi::CStr;
+/// use pam::module::PamHooks;
+/// use pam::constants::PamResultCode;
+/// use pam::context::HookContext;
 ///
 /// # fn main() {}
 /// struct MyPamModule;
-/// pam_hooks!(MyPamModule);
+/// pam_hooks!(MyPamModule, auth, account);
 ///
 /// impl PamHooks for MyPamModule {
-///    fn sm_authenticate(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+///    fn sm_authenticate(ctx: &mut HookContext) -> PamResultCode {
 ///        println!("Everybody is authenticated!");
 ///        PamResultCode::PAM_SUCCESS
 ///    }
 ///
-///    fn acct_mgmt(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+///    fn acct_mgmt(ctx: &mut HookContext) -> PamResultCode {
 ///        println!("Everybody is authorized!");
 ///        PamResultCode::PAM_SUCCESS
 ///    }
@@ -32,86 +53,62 @@
 #[macro_export]
 macro_rules! pam_hooks {
     ($ident:ident) => {
+        $crate::pam_hooks!($ident, auth, account, session, password);
+    };
+    ($ident:ident, $($group:ident),+ $(,)*) => {
         pub use self::pam_hooks_scope::*;
         mod pam_hooks_scope {
-            use std::ffi::CStr;
-            use std::os::raw::{c_char, c_int};
-            use $crate::constants::{PamFlag, PamResultCode};
-            use $crate::module::{PamHandle, PamHooks};
-
-            fn extract_argv<'a>(argc: c_int, argv: *const *const c_char) -> Vec<&'a CStr> {
-                (0..argc)
-                    .map(|o| unsafe { CStr::from_ptr(*argv.offset(o as isize) as *const c_char) })
-                    .collect()
-            }
-
-            #[no_mangle]
-            pub extern "C" fn pam_sm_acct_mgmt(
-                pamh: &mut PamHandle,
-                flags: PamFlag,
-                argc: c_int,
-                argv: *const *const c_char,
-            ) -> PamResultCode {
-                let args = extract_argv(argc, argv);
-                super::$ident::acct_mgmt(pamh, args, flags)
-            }
-
-            #[no_mangle]
-            pub extern "C" fn pam_sm_authenticate(
-                pamh: &mut PamHandle,
-                flags: PamFlag,
-                argc: c_int,
-                argv: *const *const c_char,
-            ) -> PamResultCode {
-                let args = extract_argv(argc, argv);
-                super::$ident::sm_authenticate(pamh, args, flags)
-            }
-
-            #[no_mangle]
-            pub extern "C" fn pam_sm_chauthtok(
-                pamh: &mut PamHandle,
-                flags: PamFlag,
-                argc: c_int,
-                argv: *const *const c_char,
-            ) -> PamResultCode {
-                let args = extract_argv(argc, argv);
-                super::$ident::sm_chauthtok(pamh, args, flags)
-            }
-
-            #[no_mangle]
-            pub extern "C" fn pam_sm_close_session(
-                pamh: &mut PamHandle,
-                flags: PamFlag,
-                argc: c_int,
-                argv: *const *const c_char,
-            ) -> PamResultCode {
-                let args = extract_argv(argc, argv);
-                super::$ident::sm_close_session(pamh, args, flags)
-            }
-
-            #[no_mangle]
-            pub extern "C" fn pam_sm_open_session(
-                pamh: &mut PamHandle,
-                flags: PamFlag,
-                argc: c_int,
-                argv: *const *const c_char,
-            ) -> PamResultCode {
-                let args = extract_argv(argc, argv);
-                super::$ident::sm_open_session(pamh, args, flags)
-            }
+            $($crate::pam_hooks_group!($ident, $group);)+
+        }
+    };
+}
 
-            #[no_mangle]
-            pub extern "C" fn pam_sm_setcred(
-                pamh: &mut PamHandle,
-                flags: PamFlag,
-                argc: c_int,
-                argv: *const *const c_char,
-            ) -> PamResultCode {
-                let args = extract_argv(argc, argv);
-                super::$ident::sm_setcred(pamh, args, flags)
+/// Generates the entrypoints of one management group for `pam_hooks!`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! pam_hooks_group {
+    ($ident:ident, auth) => {
+        $crate::pam_hooks_group!(@hook $ident, pam_sm_authenticate, sm_authenticate, Authenticate);
+        $crate::pam_hooks_group!(@hook $ident, pam_sm_setcred, sm_setcred, SetCred);
+    };
+    ($ident:ident, account) => {
+        $crate::pam_hooks_group!(@hook $ident, pam_sm_acct_mgmt, acct_mgmt, AcctMgmt);
+    };
+    ($ident:ident, session) => {
+        $crate::pam_hooks_group!(@hook $ident, pam_sm_open_session, sm_open_session, OpenSession);
+        $crate::pam_hooks_group!(@hook $ident, pam_sm_close_session, sm_close_session, CloseSession);
+    };
+    ($ident:ident, password) => {
+        $crate::pam_hooks_group!(@hook $ident, pam_sm_chauthtok, sm_chauthtok, ChAuthTok);
+    };
+    (@hook $ident:ident, $symbol:ident, $hook:ident, $variant:ident) => {
+        #[no_mangle]
+        #[allow(clippy::not_unsafe_ptr_arg_deref)]
+        pub extern "C" fn $symbol(
+            pamh: &mut $crate::module::PamHandle,
+            flags: $crate::constants::PamFlag,
+            argc: ::std::os::raw::c_int,
+            argv: *const *const ::std::os::raw::c_char,
+        ) -> $crate::constants::PamResultCode {
+            unsafe {
+                $crate::macros::dispatch(
+                    pamh,
+                    flags,
+                    argc,
+                    argv,
+                    $crate::context::Hook::$variant,
+                    <super::$ident as $crate::module::PamHooks>::$hook,
+                )
             }
         }
     };
+    ($ident:ident, $group:ident) => {
+        compile_error!(concat!(
+            "unknown PAM management group `",
+            stringify!($group),
+            "`, expected one of: auth, account, session, password"
+        ));
+    };
 }
 
 #[macro_export]
@@ -130,6 +127,50 @@ macro_rules! pam_try {
     };
 }
 
+/// Runs a hook on behalf of a generated `pam_sm_*` entrypoint.
+///
+/// The hook is given a `HookContext` for the call, and a panic in the hook is
+/// logged and reported to PAM as `PAM_SYSTEM_ERR` rather than unwinding into
+/// the calling application.
+///
+/// # Safety
+///
+/// `argc` and `argv` must be the arguments PAM passed to the entrypoint.
+pub unsafe fn dispatch<F>(
+    pamh: &mut PamHandle,
+    flags: PamFlag,
+    argc: c_int,
+    argv: *const *const c_char,
+    hook: Hook,
+    hook_fn: F,
+) -> PamResultCode
+where
+    F: FnOnce(&mut HookContext) -> PamResultCode,
+{
+    let args = Args::from_raw(argc, argv);
+    let pamh: *mut PamHandle = pamh;
+    match panic::catch_unwind(AssertUnwindSafe(|| {
+        hook_fn(&mut HookContext::new(
+            unsafe { &mut *pamh },
+            args,
+            flags,
+            hook,
+        ))
+    })) {
+        Ok(code) => code,
+        Err(payload) => {
+            let reason = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            // The hook's borrow of the handle ended when it unwound
+            Logger::new(unsafe { &*pamh }).error(&format!("module panicked: {}", reason));
+            PamResultCode::PAM_SYSTEM_ERR
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod test {
     use module::PamHooks;
@@ -1,9 +1,13 @@
 //! Functions for use in pam modules.
 
 use libc::c_char;
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, CString, OsString};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 
-use constants::{PamFlag, PamResultCode};
+use constants::PamResultCode;
+use context::HookContext;
+use items::{AuthTok, Item, OldAuthTok, RHost, RUser, Service, StringItem, Tty, User, UserPrompt};
+use secret::Secret;
 
 /// Opaque type, used as a pointer when making pam API calls.
 ///
@@ -61,6 +65,12 @@ pub extern "C" fn cleanup<T>(_: *const PamHandle, c_data: *mut libc::c_void, _:
 
 pub type PamResult<T> = Result<T, PamResultCode>;
 
+/// Converts `s` into a `CString` for passing to PAM, failing with `err` if it
+/// contains an interior nul byte.
+pub(crate) fn to_cstring(s: &str, err: PamResultCode) -> PamResult<CString> {
+    CString::new(s).map_err(|_| err)
+}
+
 impl PamHandle {
     /// Gets some value, identified by `key`, that has been set by the module
     /// previously.
@@ -70,14 +80,15 @@ impl PamHandle {
     ///
     /// # Errors
     ///
-    /// Returns an error if the underlying PAM function call fails.
+    /// Returns an error if the underlying PAM function call fails, or
+    /// `PAM_BUF_ERR` if the key contains a nul byte.
     ///
     /// # Safety
     ///
     /// The data stored under the provided key must be of type `T` otherwise the
     /// behaviour of this funtion is undefined.
     pub unsafe fn get_data<'a, T>(&'a self, key: &str) -> PamResult<&'a T> {
-        let c_key = CString::new(key).unwrap();
+        let c_key = to_cstring(key, PamResultCode::PAM_BUF_ERR)?;
         let mut ptr: *const libc::c_void = std::ptr::null();
         let res = pam_get_data(self, c_key.as_ptr(), &mut ptr);
         if PamResultCode::PAM_SUCCESS == res && !ptr.is_null() {
@@ -97,9 +108,10 @@ impl PamHandle {
     ///
     /// # Errors
     ///
-    /// Returns an error if the underlying PAM function call fails.
+    /// Returns an error if the underlying PAM function call fails, or
+    /// `PAM_BUF_ERR` if the key contains a nul byte.
     pub fn set_data<T>(&self, key: &str, data: Box<T>) -> PamResult<()> {
-        let c_key = CString::new(key).unwrap();
+        let c_key = to_cstring(key, PamResultCode::PAM_BUF_ERR)?;
         let res = unsafe {
             pam_set_data(
                 self,
@@ -124,7 +136,7 @@ impl PamHandle {
     /// # Errors
     ///
     /// Returns an error if the underlying PAM function call fails.
-    pub fn get_item<T: crate::items::Item>(&self) -> PamResult<Option<T>> {
+    pub fn get_item<'a, T: Item<'a>>(&'a self) -> PamResult<Option<T>> {
         let mut ptr: *const libc::c_void = std::ptr::null();
         let (res, item) = unsafe {
             let r = pam_get_item(self, T::type_id(), &mut ptr);
@@ -154,13 +166,9 @@ impl PamHandle {
     /// # Errors
     ///
     /// Returns an error if the underlying PAM function call fails.
-    ///
-    /// # Panics
-    ///
-    /// Panics if the provided item key contains a nul byte
-    pub fn set_item_str<T: crate::items::Item>(&mut self, item: T) -> PamResult<()> {
+    pub fn set_item_str<'a, T: Item<'a>>(&mut self, item: T) -> PamResult<()> {
         let res =
-            unsafe { pam_set_item(self, T::type_id(), item.into_raw().cast::<libc::c_void>())};
+            unsafe { pam_set_item(self, T::type_id(), item.into_raw().cast::<libc::c_void>()) };
         if PamResultCode::PAM_SUCCESS == res {
             Ok(())
         } else {
@@ -168,46 +176,201 @@ impl PamHandle {
         }
     }
 
-    /// Retrieves the name of the user who is authenticating or logging in.
+    /// Retrieves a string item as an owned `String`.
     ///
-    /// This is really a specialization of `get_item`.
+    /// Unlike `get_item`, the returned value does not borrow from the handle
+    /// and so remains valid after the item is changed with `set_item_str`.
     ///
-    /// See `pam_get_user` in
-    /// http://www.linux-pam.org/Linux-PAM-html/mwg-expected-by-module-item.html
+    /// # Errors
+    ///
+    /// Returns an error if the underlying PAM function call fails, or
+    /// `PAM_CONV_ERR` if the value is not valid UTF-8.
+    pub fn get_item_string<'a, T: StringItem<'a>>(&'a self) -> PamResult<Option<String>> {
+        match self.get_item::<T>()? {
+            Some(item) => item
+                .as_cstr()
+                .to_str()
+                .map(|s| Some(s.to_owned()))
+                .map_err(|_| PamResultCode::PAM_CONV_ERR),
+            None => Ok(None),
+        }
+    }
+
+    /// Retrieves a string item as an owned `OsString`, preserving values that
+    /// are not valid UTF-8.
     ///
     /// # Errors
     ///
     /// Returns an error if the underlying PAM function call fails.
+    pub fn get_item_os<'a, T: StringItem<'a>>(&'a self) -> PamResult<Option<OsString>> {
+        let item = self.get_item::<T>()?;
+        Ok(item.map(|item| std::ffi::OsStr::from_bytes(item.as_cstr().to_bytes()).to_owned()))
+    }
+
+    /// The name of the user, as set in the `PAM_USER` item.
     ///
-    /// # Panics
+    /// Unlike `get_user`, this never prompts for a username.
     ///
-    /// Panics if the provided prompt string contains a nul byte
-    pub fn get_user(&self, prompt: Option<&str>) -> PamResult<String> {
+    /// # Errors
+    ///
+    /// See `get_item_string`.
+    pub fn user(&self) -> PamResult<Option<String>> {
+        self.get_item_string::<User>()
+    }
+
+    /// The name of the remote host, as set in the `PAM_RHOST` item.
+    ///
+    /// # Errors
+    ///
+    /// See `get_item_string`.
+    pub fn rhost(&self) -> PamResult<Option<String>> {
+        self.get_item_string::<RHost>()
+    }
+
+    /// The name of the remote user, as set in the `PAM_RUSER` item.
+    ///
+    /// # Errors
+    ///
+    /// See `get_item_string`.
+    pub fn ruser(&self) -> PamResult<Option<String>> {
+        self.get_item_string::<RUser>()
+    }
+
+    /// The terminal name, as set in the `PAM_TTY` item.
+    ///
+    /// # Errors
+    ///
+    /// See `get_item_string`.
+    pub fn tty(&self) -> PamResult<Option<String>> {
+        self.get_item_string::<Tty>()
+    }
+
+    /// The name of the service that invoked PAM, as set in the `PAM_SERVICE` item.
+    ///
+    /// # Errors
+    ///
+    /// See `get_item_string`.
+    pub fn service(&self) -> PamResult<Option<String>> {
+        self.get_item_string::<Service>()
+    }
+
+    /// The current authentication token, as set in the `PAM_AUTHTOK` item by
+    /// a previous module in the stack.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying PAM function call fails.
+    pub fn authtok(&self) -> PamResult<Option<Secret>> {
+        Ok(self.get_item::<AuthTok>()?.map(|t| Secret::from_cstr(t.0)))
+    }
+
+    /// The old authentication token, as set in the `PAM_OLDAUTHTOK` item
+    /// while changing passwords.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying PAM function call fails.
+    pub fn old_authtok(&self) -> PamResult<Option<Secret>> {
+        Ok(self
+            .get_item::<OldAuthTok>()?
+            .map(|t| Secret::from_cstr(t.0)))
+    }
+
+    /// Sets the `PAM_AUTHTOK` item so later modules in the stack can use it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying PAM function call fails, or
+    /// `PAM_BUF_ERR` if the token contains a nul byte.
+    pub fn set_authtok(&mut self, token: &Secret) -> PamResult<()> {
+        let token = token.as_cstr().ok_or(PamResultCode::PAM_BUF_ERR)?;
+        self.set_item_str(AuthTok(token))
+    }
+
+    /// Sets the `PAM_OLDAUTHTOK` item so later modules in the stack can use it.
+    ///
+    /// # Errors
+    ///
+    /// See `set_authtok`.
+    pub fn set_old_authtok(&mut self, token: &Secret) -> PamResult<()> {
+        let token = token.as_cstr().ok_or(PamResultCode::PAM_BUF_ERR)?;
+        self.set_item_str(OldAuthTok(token))
+    }
+
+    /// Retrieves the name of the user who is authenticating or logging in,
+    /// exactly as PAM stores it.
+    ///
+    /// This is really a specialization of `get_item`. If the `PAM_USER` item is
+    /// not yet set, the user is prompted for it through the conversation. When
+    /// no `prompt` is given, the `UserPrompt` item is used if the application
+    /// set one, falling back to the PAM library's default ("login:").
+    ///
+    /// See `pam_get_user` in
+    /// http://www.linux-pam.org/Linux-PAM-html/mwg-expected-by-module-item.html
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying PAM function call fails, or
+    /// `PAM_BUF_ERR` if the prompt contains a nul byte.
+    pub fn get_user_cstring(&self, prompt: Option<&str>) -> PamResult<CString> {
         let ptr: *mut c_char = std::ptr::null_mut();
-        let prompt_string;
-        let c_prompt = match prompt {
-            Some(p) => {
-                prompt_string = CString::new(p).unwrap();
-                prompt_string.as_ptr()
-            }
-            None => std::ptr::null(),
+        let prompt_string = match prompt {
+            Some(p) => Some(to_cstring(p, PamResultCode::PAM_BUF_ERR)?),
+            None => self
+                .get_item::<UserPrompt>()?
+                .map(|p| p.as_cstr().to_owned()),
         };
+        let c_prompt = prompt_string
+            .as_ref()
+            .map_or(std::ptr::null(), |p| p.as_ptr());
         let res = unsafe { pam_get_user(self, &ptr, c_prompt) };
         if PamResultCode::PAM_SUCCESS == res && !ptr.is_null() {
             let const_ptr = ptr as *const c_char;
-            let bytes = unsafe { CStr::from_ptr(const_ptr).to_bytes() };
-            String::from_utf8(bytes.to_vec()).map_err(|_| PamResultCode::PAM_CONV_ERR)
+            Ok(unsafe { CStr::from_ptr(const_ptr) }.to_owned())
         } else {
             Err(res)
         }
     }
+
+    /// Retrieves the name of the user who is authenticating or logging in,
+    /// preserving names that are not valid UTF-8.
+    ///
+    /// See `get_user_cstring` for how the user is prompted.
+    ///
+    /// # Errors
+    ///
+    /// See `get_user_cstring`.
+    pub fn get_user_os(&self, prompt: Option<&str>) -> PamResult<OsString> {
+        self.get_user_cstring(prompt)
+            .map(|user| OsString::from_vec(user.into_bytes()))
+    }
+
+    /// Retrieves the name of the user who is authenticating or logging in.
+    ///
+    /// See `get_user_cstring` for how the user is prompted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying PAM function call fails, or
+    /// `PAM_CONV_ERR` if the name is not valid UTF-8. Use `get_user_os` on
+    /// systems with legacy-encoded usernames.
+    pub fn get_user(&self, prompt: Option<&str>) -> PamResult<String> {
+        let user = self.get_user_cstring(prompt)?;
+        String::from_utf8(user.into_bytes()).map_err(|_| PamResultCode::PAM_CONV_ERR)
+    }
 }
 
 /// Provides functions that are invoked by the entrypoints generated by the
 /// [`pam_hooks!` macro](../macro.pam_hooks.html).
 ///
+/// Each function is given a `HookContext` with the handle, arguments and flags
+/// of the call.
+///
 /// All of hooks are ignored by PAM dispatch by default given the default return value of `PAM_IGNORE`.
 /// Override any functions that you want to handle with your module. See `man pam(3)`.
+///
+/// Hooks are only reachable if their management group is exported; see `pam_hooks!` for how to
+/// export a subset of groups.
 #[allow(unused_variables)]
 pub trait PamHooks {
     /// This function performs the task of establishing whether the user is permitted to gain access at
@@ -215,12 +378,12 @@ pub trait PamHooks {
     /// authentication module. This function checks for other things. Such things might be: the time of
     /// day or the date, the terminal line, remote hostname, etc. This function may also determine
     /// things like the expiration on passwords, and respond that the user change it before continuing.
-    fn acct_mgmt(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+    fn acct_mgmt(ctx: &mut HookContext) -> PamResultCode {
         PamResultCode::PAM_IGNORE
     }
 
     /// This function performs the task of authenticating the user.
-    fn sm_authenticate(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+    fn sm_authenticate(ctx: &mut HookContext) -> PamResultCode {
         PamResultCode::PAM_IGNORE
     }
 
@@ -229,18 +392,20 @@ pub trait PamHooks {
     /// The PAM library calls this function twice in succession. The first time with
     /// `PAM_PRELIM_CHECK` and then, if the module does not return `PAM_TRY_AGAIN`, subsequently with
     /// `PAM_UPDATE_AUTHTOK`. It is only on the second call that the authorization token is
-    /// (possibly) changed.
-    fn sm_chauthtok(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+    /// (possibly) changed. See `pam::password` for a driver that handles both calls.
+    fn sm_chauthtok(ctx: &mut HookContext) -> PamResultCode {
         PamResultCode::PAM_IGNORE
     }
 
     /// This function is called to terminate a session.
-    fn sm_close_session(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+    ///
+    /// See `pam::session` for passing state from `sm_open_session` to this hook.
+    fn sm_close_session(ctx: &mut HookContext) -> PamResultCode {
         PamResultCode::PAM_IGNORE
     }
 
     /// This function is called to commence a session.
-    fn sm_open_session(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+    fn sm_open_session(ctx: &mut HookContext) -> PamResultCode {
         PamResultCode::PAM_IGNORE
     }
 
@@ -249,7 +414,75 @@ pub trait PamHooks {
     /// information about a user than their authentication token. This function is used to make such
     /// information available to the application. It should only be called after the user has been
     /// authenticated but before a session has been established.
-    fn sm_setcred(pamh: &mut PamHandle, args: Vec<&CStr>, flags: PamFlag) -> PamResultCode {
+    fn sm_setcred(ctx: &mut HookContext) -> PamResultCode {
         PamResultCode::PAM_IGNORE
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use conv::Inner;
+    use std::marker::PhantomData;
+
+    #[link(name = "pam")]
+    extern "C" {
+        fn pam_start(
+            service_name: *const c_char,
+            user: *const c_char,
+            pam_conversation: *const Inner,
+            pamh: &mut *mut PamHandle,
+        ) -> PamResultCode;
+
+        fn pam_end(pamh: *mut PamHandle, pam_status: PamResultCode) -> PamResultCode;
+    }
+
+    /// A handle from `pam_start` for `alice`, with `conv` as the
+    /// application's conversation, for driving hooks in tests.
+    pub(crate) struct Handle<'a> {
+        pamh: *mut PamHandle,
+        // pam_start copies the conversation, but not what it points to
+        conv: PhantomData<&'a Inner>,
+    }
+
+    impl<'a> Handle<'a> {
+        pub(crate) fn new(conv: &'a Inner) -> Handle<'a> {
+            let mut pamh = std::ptr::null_mut();
+            let res = unsafe {
+                pam_start(
+                    b"pam-rs-test\0".as_ptr().cast(),
+                    b"alice\0".as_ptr().cast(),
+                    conv,
+                    &mut pamh,
+                )
+            };
+            assert_eq!(res, PamResultCode::PAM_SUCCESS);
+            Handle {
+                pamh,
+                conv: PhantomData,
+            }
+        }
+
+        pub(crate) fn pamh(&mut self) -> &mut PamHandle {
+            unsafe { &mut *self.pamh }
+        }
+    }
+
+    impl<'a> Drop for Handle<'a> {
+        fn drop(&mut self) {
+            unsafe { pam_end(self.pamh, PamResultCode::PAM_SUCCESS) };
+        }
+    }
+
+    #[test]
+    fn nul_bytes_are_rejected_without_panicking() {
+        assert_eq!(
+            to_cstring("da\0ta", PamResultCode::PAM_BUF_ERR),
+            Err(PamResultCode::PAM_BUF_ERR)
+        );
+        assert_eq!(
+            to_cstring("data", PamResultCode::PAM_BUF_ERR).map(CString::into_bytes),
+            Ok(b"data".to_vec())
+        );
+    }
+}
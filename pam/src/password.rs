@@ -0,0 +1,289 @@
+//! A driver for `sm_chauthtok`.
+//!
+//! PAM changes passwords in two passes over the `password` stack. The first,
+//! with `PAM_PRELIM_CHECK`, only checks that every module is ready; the
+//! second, with `PAM_UPDATE_AUTHTOK`, actually changes the token. Modules are
+//! also expected to share the tokens they read through the `OldAuthTok` and
+//! `AuthTok` items, and to take the new token from the module before them
+//! when given `use_authtok`. `chauthtok` takes care of all of that, leaving
+//! the module to implement `PasswordChange`:
+//!
+//! ```no_run
+//! use pam::constants::PamResultCode;
+//! use pam::context::HookContext;
+//! use pam::module::{PamHooks, PamResult};
+//! use pam::password::{self, PasswordChange};
+//! use pam::secret::Secret;
+//!
+//! struct MyPamModule;
+//! pam::pam_hooks!(MyPamModule, password);
+//!
+//! impl PasswordChange for MyPamModule {
+//!     fn update(ctx: &mut HookContext, old: Option<&Secret>, new: &Secret) -> PamResult<()> {
+//!         // Store the new password
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl PamHooks for MyPamModule {
+//!     fn sm_chauthtok(ctx: &mut HookContext) -> PamResultCode {
+//!         password::chauthtok::<Self>(ctx)
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use constants::{PamFlag, PamResultCode, PAM_PRELIM_CHECK, PAM_UPDATE_AUTHTOK};
+use context::{Flags, HookContext};
+use conv::Conv;
+use module::PamResult;
+use secret::Secret;
+
+/// The pass of the `password` stack a `sm_chauthtok` call belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// `PAM_PRELIM_CHECK`: check that the token can be changed.
+    PrelimCheck,
+    /// `PAM_UPDATE_AUTHTOK`: change the token.
+    UpdateAuthTok,
+}
+
+impl Phase {
+    /// The phase selected by the flags `sm_chauthtok` was invoked with, if
+    /// any.
+    pub fn from_flags(flags: PamFlag) -> Option<Phase> {
+        if flags & PAM_PRELIM_CHECK != 0 {
+            Some(Phase::PrelimCheck)
+        } else if flags & PAM_UPDATE_AUTHTOK != 0 {
+            Some(Phase::UpdateAuthTok)
+        } else {
+            None
+        }
+    }
+}
+
+/// A module that can change a user's authentication token, driven by
+/// `chauthtok`.
+#[allow(unused_variables)]
+pub trait PasswordChange {
+    /// Checks that the token can be changed, e.g. that the backend storing it
+    /// is reachable. `old` is the user's current token, which is `None`
+    /// when root sets the token without it (see `chauthtok`).
+    ///
+    /// Nothing should be changed yet: another module in the stack may still
+    /// fail its own check.
+    ///
+    /// # Errors
+    ///
+    /// Returning `PAM_TRY_AGAIN` makes PAM abandon the change without calling
+    /// `update` on any module.
+    fn prelim_check(ctx: &mut HookContext, old: Option<&Secret>) -> PamResult<()> {
+        Ok(())
+    }
+
+    /// Changes the user's token from `old`, if known, to `new`.
+    ///
+    /// `pam::policy` can check the quality of `new` first.
+    ///
+    /// # Errors
+    ///
+    /// Typically `PAM_AUTHTOK_ERR` if `new` is rejected, or
+    /// `PAM_AUTHTOK_RECOVERY_ERR` if `old` is wrong.
+    fn update(ctx: &mut HookContext, old: Option<&Secret>, new: &Secret) -> PamResult<()>;
+}
+
+/// Implements `sm_chauthtok` on top of `T`'s `PasswordChange` methods.
+///
+/// In the preliminary check, the current token is taken from the
+/// `OldAuthTok` item or prompted for, and stored in `OldAuthTok` for the rest
+/// of the stack. In the update, the new token is prompted for twice and
+/// stored in `AuthTok`; with the `use_authtok` argument it is taken from
+/// `AuthTok` instead, as set by an earlier module.
+///
+/// As with `pam_unix`, root isn't asked for the current token, so that it
+/// can reset any user's, unless PAM is changing an expired token
+/// (`PAM_CHANGE_EXPIRED_AUTHTOK`). `T` then gets the `OldAuthTok` item if
+/// an earlier module set it, and `None` otherwise.
+pub fn chauthtok<T: PasswordChange>(ctx: &mut HookContext) -> PamResultCode {
+    let res = match Phase::from_flags(ctx.flags().bits()) {
+        Some(Phase::PrelimCheck) => prelim_check::<T>(ctx),
+        Some(Phase::UpdateAuthTok) => update::<T>(ctx),
+        None => Err(PamResultCode::PAM_SYSTEM_ERR),
+    };
+    match res {
+        Ok(()) => PamResultCode::PAM_SUCCESS,
+        Err(err) => err,
+    }
+}
+
+fn prelim_check<T: PasswordChange>(ctx: &mut HookContext) -> PamResult<()> {
+    let old = match ctx.handle().old_authtok()? {
+        Some(old) => Some(old),
+        None if needs_old_authtok(unsafe { libc::getuid() }, ctx.flags()) => {
+            let old = ctx.conv()?.prompt_hidden("Current password: ")?;
+            ctx.handle_mut().set_old_authtok(&old)?;
+            Some(old)
+        }
+        None => None,
+    };
+    T::prelim_check(ctx, old.as_ref())
+}
+
+fn update<T: PasswordChange>(ctx: &mut HookContext) -> PamResult<()> {
+    let use_authtok = ctx.args().has_flag("use_authtok");
+    let needs_old = needs_old_authtok(unsafe { libc::getuid() }, ctx.flags());
+    let old = ctx.handle().old_authtok()?;
+    let authtok = if use_authtok {
+        ctx.handle().authtok()?
+    } else {
+        None
+    };
+    let (old, new) = update_tokens(&ctx.conv()?, old, needs_old, authtok, use_authtok)?;
+    if !use_authtok {
+        ctx.handle_mut().set_authtok(&new)?;
+    }
+    T::update(ctx, old.as_ref(), &new)
+}
+
+/// Whether a token change by the user `uid` needs the current token: root
+/// can do without it, unless the token has expired.
+fn needs_old_authtok(uid: libc::uid_t, flags: Flags) -> bool {
+    uid != 0 || flags.change_expired_authtok()
+}
+
+/// The old and new tokens for the update, given the `OldAuthTok` item and,
+/// with `use_authtok`, the `AuthTok` item. Without `use_authtok` the new
+/// token is prompted for.
+///
+/// # Errors
+///
+/// Returns `PAM_AUTHTOK_RECOVERY_ERR` if there is no old token but one is
+/// `needed`, or `PAM_AUTHTOK_ERR` if there is no new one.
+fn update_tokens(
+    conv: &Conv,
+    old: Option<Secret>,
+    needed: bool,
+    authtok: Option<Secret>,
+    use_authtok: bool,
+) -> PamResult<(Option<Secret>, Secret)> {
+    if needed && old.is_none() {
+        return Err(PamResultCode::PAM_AUTHTOK_RECOVERY_ERR);
+    }
+    let new = if use_authtok {
+        authtok.ok_or(PamResultCode::PAM_AUTHTOK_ERR)?
+    } else {
+        new_authtok(conv)?
+    };
+    Ok((old, new))
+}
+
+/// Prompts for the new token twice, failing with `PAM_AUTHTOK_ERR` if the
+/// answers differ.
+fn new_authtok(conv: &Conv) -> PamResult<Secret> {
+    let new = conv.prompt_hidden("New password: ")?;
+    let again = conv.prompt_hidden("Retype new password: ")?;
+    if new == again {
+        Ok(new)
+    } else {
+        conv.error("Sorry, passwords do not match.")?;
+        Err(PamResultCode::PAM_AUTHTOK_ERR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use constants::{PAM_CHANGE_EXPIRED_AUTHTOK, PAM_ERROR_MSG, PAM_PROMPT_ECHO_OFF, PAM_SILENT};
+    use conv::tests::Script;
+    use items::Item;
+
+    #[test]
+    fn phases() {
+        assert_eq!(
+            Phase::from_flags(PAM_PRELIM_CHECK | PAM_SILENT),
+            Some(Phase::PrelimCheck)
+        );
+        assert_eq!(
+            Phase::from_flags(PAM_UPDATE_AUTHTOK),
+            Some(Phase::UpdateAuthTok)
+        );
+        assert_eq!(Phase::from_flags(PAM_SILENT), None);
+    }
+
+    #[test]
+    fn prompts_twice_for_the_new_token() {
+        let script = Script::new("hunter2");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(new_authtok(&conv).unwrap(), Secret::from_bytes(b"hunter2"));
+        assert_eq!(
+            script.seen(),
+            vec![
+                (PAM_PROMPT_ECHO_OFF, "New password: ".to_string()),
+                (PAM_PROMPT_ECHO_OFF, "Retype new password: ".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_tokens() {
+        let script = Script::answering(&["hunter2", "hunter3"]);
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        assert_eq!(new_authtok(&conv), Err(PamResultCode::PAM_AUTHTOK_ERR));
+        assert_eq!(
+            script.seen().last(),
+            Some(&(PAM_ERROR_MSG, "Sorry, passwords do not match.".to_string()))
+        );
+    }
+
+    #[test]
+    fn update_tokens_from_items() {
+        let script = Script::new("prompted");
+        let inner = script.inner();
+        let conv = unsafe { Conv::from_raw(&inner) };
+        let old = || Some(Secret::from_bytes(b"old"));
+        let authtok = || Some(Secret::from_bytes(b"stacked"));
+
+        assert_eq!(
+            update_tokens(&conv, old(), true, authtok(), true),
+            Ok((
+                Some(Secret::from_bytes(b"old")),
+                Secret::from_bytes(b"stacked")
+            ))
+        );
+        assert!(script.seen().is_empty());
+        assert_eq!(
+            update_tokens(&conv, old(), true, None, true),
+            Err(PamResultCode::PAM_AUTHTOK_ERR)
+        );
+        assert_eq!(
+            update_tokens(&conv, None, true, authtok(), true),
+            Err(PamResultCode::PAM_AUTHTOK_RECOVERY_ERR)
+        );
+        assert_eq!(
+            update_tokens(&conv, None, false, authtok(), true),
+            Ok((None, Secret::from_bytes(b"stacked")))
+        );
+        assert!(script.seen().is_empty());
+
+        assert_eq!(
+            update_tokens(&conv, old(), true, None, false),
+            Ok((
+                Some(Secret::from_bytes(b"old")),
+                Secret::from_bytes(b"prompted")
+            ))
+        );
+        assert_eq!(script.seen().len(), 2);
+    }
+
+    #[test]
+    fn root_needs_no_old_token() {
+        assert!(needs_old_authtok(1000, Flags::new(PAM_UPDATE_AUTHTOK)));
+        assert!(!needs_old_authtok(0, Flags::new(PAM_UPDATE_AUTHTOK)));
+        assert!(needs_old_authtok(
+            0,
+            Flags::new(PAM_UPDATE_AUTHTOK | PAM_CHANGE_EXPIRED_AUTHTOK)
+        ));
+    }
+}
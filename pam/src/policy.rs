@@ -0,0 +1,410 @@
+//! Password quality rules for modules that change passwords.
+//!
+//! `Policy` implements the common checks of `pam_pwquality` and
+//! `pam_pwhistory`, configured through the same style of module arguments:
+//!
+//! - `minlen=<n>`: the minimum length in characters (default 8),
+//! - `minclass=<n>`: the minimum number of character classes (lowercase,
+//!   uppercase, digits and others) used (default 0),
+//! - `dictpath=<file>`: a word list, one word per line, that passwords must
+//!   not be based on,
+//! - `usercheck=0`: allow passwords that contain the username,
+//! - `remember=<n>`: reject the user's last `n` passwords, as recorded by a
+//!   `History`.
+//!
+//! A `PasswordChange` implementation would typically call `Policy::enforce`
+//! at the start of `update`.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use args::Args;
+use constants::PamResultCode;
+use context::HookContext;
+use module::PamResult;
+use secret::{wipe, Secret};
+
+/// A record of the passwords users had before, for the `remember=` rule.
+pub trait History {
+    /// Whether `token` is one of the last `remember` passwords of `user`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history can't be read.
+    fn contains(&self, user: &str, token: &Secret, remember: usize) -> PamResult<bool>;
+}
+
+/// A rule broken by a new password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    TooShort { min: usize },
+    TooFewClasses { min: usize },
+    DictionaryWord,
+    ContainsUser,
+    SameAsOld,
+    Reused,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Violation::TooShort { min } => {
+                write!(f, "The password is shorter than {} characters", min)
+            }
+            Violation::TooFewClasses { min } => {
+                write!(
+                    f,
+                    "The password contains less than {} character classes",
+                    min
+                )
+            }
+            Violation::DictionaryWord => f.write_str("The password is based on a dictionary word"),
+            Violation::ContainsUser => {
+                f.write_str("The password contains the user name in some form")
+            }
+            Violation::SameAsOld => f.write_str("The password is the same as the old one"),
+            Violation::Reused => f.write_str("The password has already been used"),
+        }
+    }
+}
+
+/// A set of password quality rules.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    min_len: usize,
+    min_classes: usize,
+    dictionary: HashSet<String>,
+    user_check: bool,
+    remember: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            min_len: 8,
+            min_classes: 0,
+            dictionary: HashSet::new(),
+            user_check: true,
+            remember: 0,
+        }
+    }
+}
+
+impl Policy {
+    /// Reads the rules from the module arguments, see the module
+    /// documentation.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PAM_SERVICE_ERR` if an argument has an invalid value or the
+    /// dictionary can't be read.
+    pub fn from_args(args: &Args) -> PamResult<Policy> {
+        let mut policy = Policy::default();
+        if let Some(min_len) = parse_arg(args, "minlen")? {
+            policy.min_len = min_len;
+        }
+        if let Some(min_classes) = parse_arg(args, "minclass")? {
+            policy.min_classes = min_classes;
+        }
+        if let Some(user_check) = parse_arg::<u8>(args, "usercheck")? {
+            policy.user_check = user_check != 0;
+        }
+        if let Some(remember) = parse_arg(args, "remember")? {
+            policy.remember = remember;
+        }
+        if let Some(path) = args.get("dictpath") {
+            let words = fs::read_to_string(path).map_err(|_| PamResultCode::PAM_SERVICE_ERR)?;
+            policy = policy.with_dictionary(words.lines());
+        }
+        Ok(policy)
+    }
+
+    /// Adds `words` to the dictionary passwords must not be based on.
+    pub fn with_dictionary<'w, I: IntoIterator<Item = &'w str>>(mut self, words: I) -> Policy {
+        self.dictionary.extend(
+            words
+                .into_iter()
+                .map(str::trim)
+                .filter(|w| !w.is_empty())
+                .map(str::to_lowercase),
+        );
+        self
+    }
+
+    /// The number of previous passwords a `History` is asked about.
+    pub fn remember(&self) -> usize {
+        self.remember
+    }
+
+    /// Checks `new` against every rule, returning those it breaks.
+    ///
+    /// `old` is the password being replaced, if known. `history` is only
+    /// consulted if `remember=` was given.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error from `history`.
+    pub fn check(
+        &self,
+        user: &str,
+        old: Option<&Secret>,
+        new: &Secret,
+        history: Option<&dyn History>,
+    ) -> PamResult<Vec<Violation>> {
+        let mut violations = Vec::new();
+
+        if chars(new.expose()).count() < self.min_len {
+            violations.push(Violation::TooShort { min: self.min_len });
+        }
+        if classes(chars(new.expose())) < self.min_classes {
+            violations.push(Violation::TooFewClasses {
+                min: self.min_classes,
+            });
+        }
+
+        // Every copy of the password is a `Secret`, so they're all wiped
+        let lower = collect_secret(chars(new.expose()).flat_map(char::to_lowercase), new.len());
+        let lower = lower.expose_str().unwrap_or("");
+        if self.is_dictionary_word(lower) {
+            violations.push(Violation::DictionaryWord);
+        }
+        if self.user_check && contains_user(lower, &user.to_lowercase()) {
+            violations.push(Violation::ContainsUser);
+        }
+
+        if old.is_some_and(|old| old == new) {
+            violations.push(Violation::SameAsOld);
+        } else if self.remember > 0 {
+            if let Some(history) = history {
+                if history.contains(user, new, self.remember)? {
+                    violations.push(Violation::Reused);
+                }
+            }
+        }
+        Ok(violations)
+    }
+
+    /// Checks `new` for the user being handled by `ctx`, showing each broken
+    /// rule to the user as an error message.
+    ///
+    /// # Errors
+    ///
+    /// Returns `PAM_AUTHTOK_ERR` if any rule is broken, or the error from
+    /// the conversation or `history`.
+    pub fn enforce(
+        &self,
+        ctx: &HookContext,
+        old: Option<&Secret>,
+        new: &Secret,
+        history: Option<&dyn History>,
+    ) -> PamResult<()> {
+        let user = ctx.user()?;
+        let violations = self.check(&user, old, new, history)?;
+        if violations.is_empty() {
+            return Ok(());
+        }
+        let conv = ctx.conv()?;
+        for violation in &violations {
+            conv.error(&format!("BAD PASSWORD: {}", violation))?;
+        }
+        Err(PamResultCode::PAM_AUTHTOK_ERR)
+    }
+
+    /// Whether `lower`, ignoring digits and symbols around it or written
+    /// backwards, is a dictionary word.
+    fn is_dictionary_word(&self, lower: &str) -> bool {
+        if self.dictionary.is_empty() {
+            return false;
+        }
+        let stem = lower.trim_matches(|c: char| !c.is_alphabetic());
+        let reversed = collect_secret(stem.chars().rev(), stem.len());
+        self.dictionary.contains(lower)
+            || self.dictionary.contains(stem)
+            || reversed
+                .expose_str()
+                .is_some_and(|reversed| self.dictionary.contains(reversed))
+    }
+}
+
+fn parse_arg<T: FromStr>(args: &Args, key: &str) -> PamResult<Option<T>> {
+    match args.get(key) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| PamResultCode::PAM_SERVICE_ERR),
+        None => Ok(None),
+    }
+}
+
+/// The characters of `bytes`, with invalid UTF-8 replaced by U+FFFD, without
+/// copying them to the heap as `String::from_utf8_lossy` can.
+fn chars(bytes: &[u8]) -> impl Iterator<Item = char> + '_ {
+    bytes.utf8_chunks().flat_map(|chunk| {
+        let invalid = if chunk.invalid().is_empty() {
+            None
+        } else {
+            Some(char::REPLACEMENT_CHARACTER)
+        };
+        chunk.valid().chars().chain(invalid)
+    })
+}
+
+/// Collects `chars` into a `Secret`. The buffer starts with `capacity` bytes
+/// and is grown by hand, wiping the old one, so that no reallocation leaves
+/// an unwiped copy behind.
+fn collect_secret<I: IntoIterator<Item = char>>(chars: I, capacity: usize) -> Secret {
+    let mut buf = Vec::with_capacity(capacity);
+    let mut utf8 = [0; 4];
+    for c in chars {
+        let encoded = c.encode_utf8(&mut utf8).as_bytes();
+        if buf.len() + encoded.len() > buf.capacity() {
+            let mut bigger = Vec::with_capacity(buf.capacity() * 2 + encoded.len());
+            bigger.extend_from_slice(&buf);
+            wipe(&mut buf);
+            buf = bigger;
+        }
+        buf.extend_from_slice(encoded);
+    }
+    wipe(&mut utf8);
+    Secret::new(buf)
+}
+
+/// The number of character classes used in `chars`.
+fn classes<I: IntoIterator<Item = char>>(chars: I) -> usize {
+    let (mut lower, mut upper, mut digit, mut other) = (false, false, false, false);
+    for c in chars {
+        match c {
+            'a'..='z' => lower = true,
+            'A'..='Z' => upper = true,
+            '0'..='9' => digit = true,
+            _ => other = true,
+        }
+    }
+    [lower, upper, digit, other]
+        .iter()
+        .filter(|&&used| used)
+        .count()
+}
+
+/// Whether `lower` contains `user`, forwards or backwards. Very short names
+/// are ignored, as they would reject too many passwords.
+fn contains_user(lower: &str, user: &str) -> bool {
+    if user.chars().count() < 3 {
+        return false;
+    }
+    let reversed: String = user.chars().rev().collect();
+    lower.contains(user) || lower.contains(&reversed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use args::tests::with_args;
+    use std::cell::Cell;
+
+    fn check(policy: &Policy, new: &str) -> Vec<Violation> {
+        policy
+            .check("alice", None, &Secret::from_bytes(new.as_bytes()), None)
+            .unwrap()
+    }
+
+    struct Remembers(&'static str, Cell<usize>);
+
+    impl History for Remembers {
+        fn contains(&self, _: &str, token: &Secret, remember: usize) -> PamResult<bool> {
+            self.1.set(remember);
+            Ok(token.expose() == self.0.as_bytes())
+        }
+    }
+
+    #[test]
+    fn length_and_classes() {
+        let policy = Policy {
+            min_classes: 3,
+            ..Policy::default()
+        };
+        assert_eq!(
+            check(&policy, "short"),
+            [
+                Violation::TooShort { min: 8 },
+                Violation::TooFewClasses { min: 3 }
+            ]
+        );
+        assert_eq!(check(&policy, "Longer-one"), []);
+        assert_eq!(
+            check(&policy, "ünïcödéé"),
+            [Violation::TooFewClasses { min: 3 }]
+        );
+    }
+
+    #[test]
+    fn dictionary_and_username() {
+        let policy = Policy::default().with_dictionary("Dragon\nsunshine\n".lines());
+        assert_eq!(check(&policy, "sunshine"), [Violation::DictionaryWord]);
+        assert_eq!(check(&policy, "!Dragon2024"), [Violation::DictionaryWord]);
+        assert_eq!(check(&policy, "enihsnus"), [Violation::DictionaryWord]);
+        assert_eq!(check(&policy, "xxALICExx"), [Violation::ContainsUser]);
+        assert_eq!(check(&policy, "ecila-1234"), [Violation::ContainsUser]);
+        let lax = Policy {
+            user_check: false,
+            ..policy
+        };
+        assert_eq!(check(&lax, "xxALICExx"), []);
+    }
+
+    #[test]
+    fn old_and_history() {
+        let policy = Policy {
+            remember: 5,
+            ..Policy::default()
+        };
+        let history = Remembers("correct horse", Cell::new(0));
+        let old = Secret::from_bytes(b"battery staple");
+        let check = |new: &[u8]| {
+            policy
+                .check(
+                    "alice",
+                    Some(&old),
+                    &Secret::from_bytes(new),
+                    Some(&history),
+                )
+                .unwrap()
+        };
+        assert_eq!(check(b"battery staple"), [Violation::SameAsOld]);
+        assert_eq!(check(b"correct horse"), [Violation::Reused]);
+        assert_eq!(history.1.get(), 5);
+        assert_eq!(check(b"tr0ub4dor&3"), []);
+    }
+
+    #[test]
+    fn secret_copies() {
+        let lower = collect_secret("ÅNGSTRÖM".chars().flat_map(char::to_lowercase), 1);
+        assert_eq!(lower.expose_str(), Some("ångström"));
+        assert_eq!(chars(b"a\xffb").collect::<String>(), "a\u{fffd}b");
+        let policy = Policy::default().with_dictionary(["dragon"]);
+        assert_eq!(
+            check(&policy, "NOGARD"),
+            [Violation::TooShort { min: 8 }, Violation::DictionaryWord]
+        );
+    }
+
+    #[test]
+    fn from_args() {
+        let policy = with_args(
+            &["minlen=12", "minclass=2", "usercheck=0", "remember=3"],
+            |args| Policy::from_args(&args),
+        )
+        .unwrap();
+        assert_eq!(policy.min_len, 12);
+        assert_eq!(policy.min_classes, 2);
+        assert!(!policy.user_check);
+        assert_eq!(policy.remember(), 3);
+
+        assert_eq!(
+            with_args(&["minlen=eight"], |args| Policy::from_args(&args)).err(),
+            Some(PamResultCode::PAM_SERVICE_ERR)
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! A per-process tokio runtime for modules that call async backends.
+//!
+//! Hooks are synchronous, so a module that uses async clients needs a runtime
+//! to `block_on`. Building one per call is expensive, so `block_on` shares a
+//! single current-thread runtime between all calls in the process.
+//!
+//! Applications like sshd and login fork between the auth and session
+//! phases. A runtime built before the fork is unusable in the child, so the
+//! runtime is kept in a `ForkSafe` and rebuilt in the child.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::runtime::{Builder, Runtime};
+
+use constants::PamResultCode;
+use fork::ForkSafe;
+use module::PamResult;
+
+static RUNTIME: ForkSafe<Runtime> = ForkSafe::new();
+
+fn runtime() -> PamResult<Arc<Runtime>> {
+    RUNTIME.get_or_try_init(|| {
+        Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|_| PamResultCode::PAM_SYSTEM_ERR)
+    })
+}
+
+/// Runs `future` to completion on the shared runtime.
+///
+/// # Errors
+///
+/// Returns `PAM_SYSTEM_ERR` if the runtime can't be built.
+pub fn block_on<F: Future>(future: F) -> PamResult<F::Output> {
+    Ok(runtime()?.block_on(future))
+}
+
+/// Runs `future` to completion on the shared runtime, giving up once
+/// `deadline` has elapsed.
+///
+/// # Errors
+///
+/// Returns `PAM_AUTHINFO_UNAVAIL` if the deadline elapses, or
+/// `PAM_SYSTEM_ERR` if the runtime can't be built.
+pub fn block_on_with_deadline<F: Future>(
+    deadline: Option<Duration>,
+    future: F,
+) -> PamResult<F::Output> {
+    match deadline {
+        Some(deadline) => {
+            let rt = runtime()?;
+            let _guard = rt.enter();
+            rt.block_on(tokio::time::timeout(deadline, future))
+                .map_err(|_| PamResultCode::PAM_AUTHINFO_UNAVAIL)
+        }
+        None => block_on(future),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+
+    #[test]
+    fn runs_futures_and_enforces_deadlines() {
+        assert_eq!(block_on(future::ready(42)), Ok(42));
+        assert_eq!(
+            block_on_with_deadline(Some(Duration::from_millis(10)), future::pending::<()>()),
+            Err(PamResultCode::PAM_AUTHINFO_UNAVAIL)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! A container for authentication tokens.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::ptr;
+use std::sync::atomic::{self, Ordering};
+
+/// Overwrites `buf` with zeros in a way the compiler won't optimize away.
+pub(crate) fn wipe(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    atomic::compiler_fence(Ordering::SeqCst);
+}
+
+/// A password or other authentication token.
+///
+/// `Secret` keeps tokens out of logs and memory dumps as far as it can:
+///
+/// - `Debug` prints `Secret(<redacted>)` instead of the value,
+/// - it doesn't implement `Clone`, so copies must be made explicitly,
+/// - its buffer is overwritten with zeros when it is dropped,
+/// - `lock` can keep its buffer from being swapped to disk.
+///
+/// The value is stored nul-terminated so it can be handed back to PAM, e.g.
+/// as the `AuthTok` item.
+pub struct Secret {
+    // Always ends with a nul byte
+    buf: Vec<u8>,
+    locked: bool,
+}
+
+impl Secret {
+    /// Takes ownership of `bytes`, which are wiped once copied.
+    pub fn new(mut bytes: Vec<u8>) -> Secret {
+        let secret = Secret::from_bytes(&bytes);
+        wipe(&mut bytes);
+        secret
+    }
+
+    /// Copies `bytes` into a new `Secret`.
+    pub fn from_bytes(bytes: &[u8]) -> Secret {
+        // Allocated up front so the buffer is never reallocated, which would
+        // leave an unwiped copy behind
+        let mut buf = Vec::with_capacity(bytes.len() + 1);
+        buf.extend_from_slice(bytes);
+        buf.push(0);
+        Secret { buf, locked: false }
+    }
+
+    pub fn from_cstr(value: &CStr) -> Secret {
+        Secret::from_bytes(value.to_bytes())
+    }
+
+    /// The secret value, without the nul terminator.
+    pub fn expose(&self) -> &[u8] {
+        match self.buf.split_last() {
+            Some((_, value)) => value,
+            None => &[],
+        }
+    }
+
+    /// The secret value, if it is valid UTF-8.
+    pub fn expose_str(&self) -> Option<&str> {
+        std::str::from_utf8(self.expose()).ok()
+    }
+
+    /// The secret value as a C string, if it has no interior nul bytes.
+    pub fn as_cstr(&self) -> Option<&CStr> {
+        CStr::from_bytes_with_nul(&self.buf).ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buf.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Locks the secret's buffer into memory with `mlock`, so that it is
+    /// never written to swap.
+    ///
+    /// # Errors
+    ///
+    /// Returns the OS error if the buffer can't be locked, e.g. because
+    /// `RLIMIT_MEMLOCK` has been reached.
+    pub fn lock(&mut self) -> io::Result<()> {
+        if self.locked {
+            return Ok(());
+        }
+        let res = unsafe { libc::mlock(self.buf.as_ptr().cast(), self.buf.capacity()) };
+        if res == 0 {
+            self.locked = true;
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        wipe(&mut self.buf);
+        if self.locked {
+            unsafe { libc::munlock(self.buf.as_ptr().cast(), self.buf.capacity()) };
+        }
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(<redacted>)")
+    }
+}
+
+/// Compares secrets without exiting early on the first difference, so the
+/// time taken reveals only whether the lengths differ.
+impl PartialEq for Secret {
+    fn eq(&self, other: &Secret) -> bool {
+        self.len() == other.len()
+            && self
+                .expose()
+                .iter()
+                .zip(other.expose())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+impl Eq for Secret {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_and_exposes() {
+        let secret = Secret::new(b"hunter2".to_vec());
+        assert_eq!(format!("{:?}", secret), "Secret(<redacted>)");
+        assert_eq!(secret.expose(), b"hunter2");
+        assert_eq!(secret.expose_str(), Some("hunter2"));
+        assert_eq!(secret.as_cstr().map(CStr::to_bytes), Some(&b"hunter2"[..]));
+        assert_eq!(Secret::from_bytes(b"a\0b").as_cstr(), None);
+    }
+
+    #[test]
+    fn compares_values() {
+        assert_eq!(Secret::from_bytes(b"abc"), Secret::from_bytes(b"abc"));
+        assert_ne!(Secret::from_bytes(b"abc"), Secret::from_bytes(b"abd"));
+        assert_ne!(Secret::from_bytes(b"abc"), Secret::from_bytes(b"ab"));
+    }
+
+    #[test]
+    fn wipes_buffers() {
+        let mut buf = b"hunter2".to_vec();
+        wipe(&mut buf);
+        assert_eq!(buf, [0; 7]);
+    }
+}
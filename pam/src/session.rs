@@ -0,0 +1,270 @@
+//! Correlating `sm_open_session` with `sm_close_session`.
+//!
+//! The two session hooks are separate calls, but a module usually needs to
+//! know in `sm_close_session` what it did in `sm_open_session`: which session
+//! it registered, which mount it made. Implementing `Session` and calling
+//! `open_session` and `close_session` from the hooks keeps that state in the
+//! PAM handle's module data in between:
+//!
+//! ```no_run
+//! use pam::constants::PamResultCode;
+//! use pam::context::HookContext;
+//! use pam::module::{PamHooks, PamResult};
+//! use pam::session::{self, Session};
+//!
+//! struct MyPamModule;
+//! pam::pam_hooks!(MyPamModule, session);
+//!
+//! impl Session for MyPamModule {
+//!     type State = u64;
+//!
+//!     fn open(ctx: &mut HookContext) -> PamResult<u64> {
+//!         // Register the session somewhere
+//!         Ok(42)
+//!     }
+//!
+//!     fn close(ctx: &mut HookContext, id: u64) -> PamResult<()> {
+//!         // Unregister session `id`
+//!         Ok(())
+//!     }
+//! }
+//!
+//! impl PamHooks for MyPamModule {
+//!     fn sm_open_session(ctx: &mut HookContext) -> PamResultCode {
+//!         session::open_session::<Self>(ctx)
+//!     }
+//!
+//!     fn sm_close_session(ctx: &mut HookContext) -> PamResultCode {
+//!         session::close_session::<Self>(ctx)
+//!     }
+//! }
+//! # fn main() {}
+//! ```
+
+use std::any;
+use std::cell::Cell;
+
+use constants::PamResultCode;
+use context::HookContext;
+use module::PamResult;
+
+/// A module whose sessions carry state from opening to closing, driven by
+/// `open_session` and `close_session`.
+pub trait Session {
+    /// What `close` needs to know about the session.
+    ///
+    /// If the session is never closed, e.g. because the application exits
+    /// first, the state is dropped when the PAM handle is ended, so its
+    /// `Drop` implementation can release anything `open` acquired.
+    type State: 'static;
+
+    /// Opens a session.
+    ///
+    /// # Errors
+    ///
+    /// Typically `PAM_SESSION_ERR`.
+    fn open(ctx: &mut HookContext) -> PamResult<Self::State>;
+
+    /// Closes the session opened with `state`.
+    ///
+    /// # Errors
+    ///
+    /// Typically `PAM_SESSION_ERR`.
+    fn close(ctx: &mut HookContext, state: Self::State) -> PamResult<()>;
+}
+
+/// The module data key `T`'s state is stored under.
+fn key<T: Session>() -> String {
+    format!("pam::session::{}", any::type_name::<T>())
+}
+
+/// Where session state is kept between the hooks. PAM only lets modules use
+/// the handle's module data, so tests keep it in memory instead.
+trait Store {
+    /// Stores `T`'s state, dropping any it had already.
+    fn set<T: Session>(&self, ctx: &HookContext, state: T::State) -> PamResult<()>;
+
+    /// Takes `T`'s state, if it has any.
+    fn take<T: Session>(&self, ctx: &HookContext) -> Option<T::State>;
+}
+
+/// The PAM handle's module data.
+struct ModuleData;
+
+impl Store for ModuleData {
+    fn set<T: Session>(&self, ctx: &HookContext, state: T::State) -> PamResult<()> {
+        ctx.handle()
+            .set_data(&key::<T>(), Box::new(Cell::new(Some(state))))
+    }
+
+    fn take<T: Session>(&self, ctx: &HookContext) -> Option<T::State> {
+        // The data under this key is only ever set by `set::<T>`
+        match unsafe { ctx.handle().get_data::<Cell<Option<T::State>>>(&key::<T>()) } {
+            Ok(cell) => cell.take(),
+            Err(_) => None,
+        }
+    }
+}
+
+/// Implements `sm_open_session` with `T::open`, storing the state it returns
+/// for `close_session`.
+///
+/// Opening a second session on the same handle drops the first one's state.
+pub fn open_session<T: Session>(ctx: &mut HookContext) -> PamResultCode {
+    open::<T>(ctx, &ModuleData)
+}
+
+/// Implements `sm_close_session` with `T::close`, passing it the state stored
+/// by `open_session`.
+///
+/// Returns `PAM_IGNORE` if there is no session to close: it wasn't opened
+/// through this handle, or has been closed already.
+pub fn close_session<T: Session>(ctx: &mut HookContext) -> PamResultCode {
+    close::<T>(ctx, &ModuleData)
+}
+
+fn open<T: Session>(ctx: &mut HookContext, store: &impl Store) -> PamResultCode {
+    let state = match T::open(ctx) {
+        Ok(state) => state,
+        Err(err) => return err,
+    };
+    match store.set::<T>(ctx, state) {
+        Ok(()) => PamResultCode::PAM_SUCCESS,
+        Err(err) => err,
+    }
+}
+
+fn close<T: Session>(ctx: &mut HookContext, store: &impl Store) -> PamResultCode {
+    let state = match store.take::<T>(ctx) {
+        Some(state) => state,
+        None => {
+            ctx.log().debug("no open session to close");
+            return PamResultCode::PAM_IGNORE;
+        }
+    };
+    match T::close(ctx, state) {
+        Ok(()) => PamResultCode::PAM_SUCCESS,
+        Err(err) => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use args::tests::with_args;
+    use context::Hook;
+    use conv::tests::Script;
+    use module::tests::Handle;
+    use std::any::Any;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    thread_local! {
+        /// The states `Mount::close` was given.
+        static CLOSED: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    struct Mount;
+    struct Register;
+
+    impl Session for Mount {
+        type State = String;
+
+        fn open(_: &mut HookContext) -> PamResult<String> {
+            Ok("/home".to_owned())
+        }
+
+        fn close(_: &mut HookContext, state: String) -> PamResult<()> {
+            CLOSED.with(|closed| closed.borrow_mut().push(state));
+            Ok(())
+        }
+    }
+
+    impl Session for Register {
+        type State = u64;
+
+        fn open(_: &mut HookContext) -> PamResult<u64> {
+            Ok(1)
+        }
+
+        fn close(_: &mut HookContext, _: u64) -> PamResult<()> {
+            Ok(())
+        }
+    }
+
+    /// Session state kept in memory.
+    #[derive(Default)]
+    struct Memory(RefCell<HashMap<String, Box<dyn Any>>>);
+
+    impl Store for Memory {
+        fn set<T: Session>(&self, _: &HookContext, state: T::State) -> PamResult<()> {
+            self.0.borrow_mut().insert(key::<T>(), Box::new(state));
+            Ok(())
+        }
+
+        fn take<T: Session>(&self, _: &HookContext) -> Option<T::State> {
+            let state = self.0.borrow_mut().remove(&key::<T>())?;
+            state.downcast().ok().map(|state| *state)
+        }
+    }
+
+    /// Runs `hook`, `OpenSession` or `CloseSession`, for `T` on `handle`,
+    /// keeping the state in `store`.
+    fn run<T: Session>(handle: &mut Handle, store: &Memory, hook: Hook) -> PamResultCode {
+        with_args(&[], |args| {
+            let mut ctx = HookContext::new(handle.pamh(), args, 0, hook);
+            match hook {
+                Hook::OpenSession => open::<T>(&mut ctx, store),
+                _ => close::<T>(&mut ctx, store),
+            }
+        })
+    }
+
+    #[test]
+    fn each_module_has_its_own_key() {
+        assert_ne!(key::<Mount>(), key::<Register>());
+        assert!(key::<Mount>().ends_with("::Mount"));
+    }
+
+    #[test]
+    fn close_gets_the_state_from_open() {
+        let script = Script::new("");
+        let inner = script.inner();
+        let mut handle = Handle::new(&inner);
+        let store = Memory::default();
+        assert_eq!(
+            run::<Mount>(&mut handle, &store, Hook::OpenSession),
+            PamResultCode::PAM_SUCCESS
+        );
+        // Another module's session wasn't opened
+        assert_eq!(
+            run::<Register>(&mut handle, &store, Hook::CloseSession),
+            PamResultCode::PAM_IGNORE
+        );
+        assert_eq!(
+            run::<Mount>(&mut handle, &store, Hook::CloseSession),
+            PamResultCode::PAM_SUCCESS
+        );
+        CLOSED.with(|closed| assert_eq!(*closed.borrow(), ["/home"]));
+    }
+
+    #[test]
+    fn close_without_open_is_ignored() {
+        let script = Script::new("");
+        let inner = script.inner();
+        let mut handle = Handle::new(&inner);
+        let store = Memory::default();
+        assert_eq!(
+            run::<Mount>(&mut handle, &store, Hook::CloseSession),
+            PamResultCode::PAM_IGNORE
+        );
+
+        run::<Mount>(&mut handle, &store, Hook::OpenSession);
+        run::<Mount>(&mut handle, &store, Hook::CloseSession);
+        assert_eq!(
+            run::<Mount>(&mut handle, &store, Hook::CloseSession),
+            PamResultCode::PAM_IGNORE
+        );
+        CLOSED.with(|closed| assert_eq!(closed.borrow().len(), 1));
+    }
+}